@@ -0,0 +1,85 @@
+//! Stress test for the parallax layers: a large city of buildings with the camera flying over it
+//!
+//! Run with `cargo run --release --example parallax_bench` and watch the logged frame times.
+
+use bevy::{
+    asset::AssetMetaCheck,
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+};
+use fgj_2024::{
+    atlas_loader::AtlasPlugin,
+    constants::TILE_SIZE,
+    parallax::{ParallaxGrid, ParallaxPlugin},
+    plugins::CorePlugin,
+    tilemap::{spawn_tiles, TileType},
+};
+
+/// Width and height of the map in tiles
+const MAP_SIZE: usize = 100;
+/// Tiles per second the camera flies at
+const CAMERA_SPEED: f32 = 4.;
+
+fn main() {
+    App::new()
+        .insert_resource(AssetMetaCheck::Never)
+        .add_plugins((
+            DefaultPlugins.set(ImagePlugin::default_nearest()),
+            FrameTimeDiagnosticsPlugin,
+            LogDiagnosticsPlugin::default(),
+            CorePlugin,
+            AtlasPlugin,
+            ParallaxPlugin,
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (fly_camera, report_culling))
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    // A grid of city blocks, three by three buildings each
+    let map: Vec<Vec<TileType>> = (0..MAP_SIZE)
+        .map(|i| {
+            (0..MAP_SIZE)
+                .map(|j| {
+                    if i % 4 == 0 || j % 4 == 0 {
+                        TileType::Road
+                    } else {
+                        TileType::Building
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    spawn_tiles(&mut commands, &map);
+
+    let mut camera = Camera2dBundle::default();
+    camera.transform.translation.z = 65.;
+    camera.projection.scale = 1.3;
+    commands.spawn(camera);
+}
+
+/// Circles over the whole map so objects keep entering and leaving the view
+fn fly_camera(time: Res<Time>, mut camera_q: Query<&mut Transform, With<Camera2d>>) {
+    let center = Vec2::splat(MAP_SIZE as f32 * TILE_SIZE / 2.);
+    let radius = 0.4 * MAP_SIZE as f32 * TILE_SIZE;
+    let angle = time.elapsed_seconds() * CAMERA_SPEED * TILE_SIZE / radius;
+    for mut transform in camera_q.iter_mut() {
+        let pos = center + radius * Vec2::from_angle(angle);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+    }
+}
+
+fn report_culling(time: Res<Time>, mut timer: Local<Timer>, grid: Res<ParallaxGrid>) {
+    if timer.duration().is_zero() {
+        *timer = Timer::from_seconds(2., TimerMode::Repeating);
+    }
+    if timer.tick(time.delta()).just_finished() {
+        info!(
+            "{} of {} parallax objects in view",
+            grid.visible_count(),
+            grid.object_count()
+        );
+    }
+}
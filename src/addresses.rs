@@ -0,0 +1,348 @@
+//! Street and district names, and the addresses of places in the city
+
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::{Car, Player},
+    constants::{Constants, TILE_SIZE},
+    missions::{MissionState, Ride},
+    plugins::GameSet,
+    tilemap::TileType,
+};
+
+static LEVEL_ADDRESSES: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/tilemap/simplified/Level_0/addresses.json"
+));
+
+/// Names of the streets in generated cities, repeating if there are more roads than names
+const STREET_NAMES: [&str; 16] = [
+    "Tango", "Harbor", "Foundry", "Linden", "Market", "Chapel", "Willow", "Copper", "Station",
+    "Meadow", "Lantern", "Granite", "Orchard", "Beacon", "Ferry", "Juniper",
+];
+
+/// Names of the districts around downtown in generated cities
+const DISTRICT_NAMES: [&str; 8] = [
+    "Old Town",
+    "Docklands",
+    "Hillcrest",
+    "Southside",
+    "Northgate",
+    "Riverside",
+    "Millbrook",
+    "Eastfield",
+];
+
+/// Road tiles in a straight line it takes to make a street
+const MIN_STREET_TILES: i32 = 3;
+
+pub struct AddressPlugin;
+
+impl Plugin for AddressPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_address_ui)
+            .add_systems(Update, update_address_ui.in_set(GameSet::Ui));
+    }
+}
+
+/// A straight street between two road tiles
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Street {
+    pub name: String,
+    pub from: IVec2,
+    pub to: IVec2,
+}
+
+impl Street {
+    /// Distance in tiles from `pos` to the street, and the house number there. Numbers grow
+    /// away from `from`, odd on the left side of the street and even on the right
+    fn locate(&self, pos: Vec2) -> (f32, u32) {
+        let from = self.from.as_vec2();
+        let span = (self.to - self.from).as_vec2();
+        let direction = span.normalize_or_zero();
+        let along = (pos - from).dot(direction).clamp(0., span.length());
+        let distance = pos.distance(from + direction * along);
+        let right = direction.perp_dot(pos - from) < 0.;
+        (distance, 2 * along.round() as u32 + 1 + right as u32)
+    }
+}
+
+/// An area of the city, covering the places closer to its center than to other districts
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct District {
+    pub name: String,
+    pub center: IVec2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub number: u32,
+    pub street: String,
+    pub district: Option<String>,
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.number, self.street)?;
+        if let Some(district) = &self.district {
+            write!(f, ", {district}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Streets and districts of the city being played, positions in tiles
+#[derive(Resource, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct AddressBook {
+    pub streets: Vec<Street>,
+    pub districts: Vec<District>,
+}
+
+impl AddressBook {
+    /// Names of the hand made `Level_0` map
+    pub fn load() -> Self {
+        match serde_json::from_str(LEVEL_ADDRESSES) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                error!("failed parsing addresses.json: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Names every straight road of the map, the names picked by `seed`. Roads on the same
+    /// row or column share their name, and the districts split the map in a 3x3 grid
+    pub fn generate(tiles: &[Vec<TileType>], seed: u64) -> Self {
+        let offset = (seed % STREET_NAMES.len() as u64) as usize;
+        let name = |line: usize, suffix: &str| {
+            format!(
+                "{} {suffix}",
+                STREET_NAMES[(offset + line) % STREET_NAMES.len()]
+            )
+        };
+
+        let (rows, columns) = street_runs(tiles);
+        let mut streets = Vec::new();
+        // Avenues start halfway through the names so they don't mirror the streets
+        for (runs, suffix, first) in [
+            (rows, "Street", 0),
+            (columns, "Avenue", STREET_NAMES.len() / 2),
+        ] {
+            let line_of = |tile: IVec2| if suffix == "Street" { tile.y } else { tile.x };
+            let mut lines: Vec<i32> = runs.iter().map(|(from, _)| line_of(*from)).collect();
+            lines.dedup();
+            streets.extend(runs.into_iter().map(|(from, to)| {
+                let line = lines.iter().position(|l| *l == line_of(from)).unwrap_or(0);
+                Street {
+                    name: name(first + line, suffix),
+                    from,
+                    to,
+                }
+            }));
+        }
+
+        let size = IVec2::new(tiles.first().map_or(0, Vec::len) as i32, tiles.len() as i32);
+        let mut names = DISTRICT_NAMES.iter().cycle().skip(offset);
+        let districts = (0..9)
+            .map(|i| {
+                let cell = IVec2::new(i % 3, i / 3);
+                let name = if cell == IVec2::ONE {
+                    "Downtown"
+                } else {
+                    names.next().copied().unwrap_or_default()
+                };
+                District {
+                    name: name.into(),
+                    center: (cell * 2 + IVec2::ONE) * size / 6,
+                }
+            })
+            .collect();
+        Self { streets, districts }
+    }
+
+    /// District closest to the world position `pos`
+    pub fn district(&self, pos: Vec2) -> Option<&str> {
+        let tile = pos / TILE_SIZE;
+        self.districts
+            .iter()
+            .min_by(|a, b| {
+                let a = a.center.as_vec2().distance_squared(tile);
+                let b = b.center.as_vec2().distance_squared(tile);
+                a.total_cmp(&b)
+            })
+            .map(|district| district.name.as_str())
+    }
+
+    /// Address on the street closest to the world position `pos`
+    pub fn address(&self, pos: Vec2) -> Option<Address> {
+        let tile = pos / TILE_SIZE;
+        let (street, (_, number)) = self
+            .streets
+            .iter()
+            .map(|street| (street, street.locate(tile)))
+            .min_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))?;
+        Some(Address {
+            number,
+            street: street.name.clone(),
+            district: self.district(pos).map(String::from),
+        })
+    }
+}
+
+/// Straight runs of road tiles long enough to be streets, rows first and then columns
+fn street_runs(tiles: &[Vec<TileType>]) -> (Vec<(IVec2, IVec2)>, Vec<(IVec2, IVec2)>) {
+    let is_road = |tile: IVec2| {
+        usize::try_from(tile.y)
+            .ok()
+            .zip(usize::try_from(tile.x).ok())
+            .and_then(|(y, x)| tiles.get(y)?.get(x))
+            .is_some_and(|tp| tp.is_road())
+    };
+    let height = tiles.len() as i32;
+    let width = tiles.first().map_or(0, Vec::len) as i32;
+
+    let runs = |lines: i32, len: i32, at: &dyn Fn(i32, i32) -> IVec2| {
+        let mut runs = Vec::new();
+        for line in 0..lines {
+            let mut start = None;
+            for i in 0..=len {
+                match (start, is_road(at(line, i))) {
+                    (None, true) => start = Some(i),
+                    (Some(s), false) => {
+                        if i - s >= MIN_STREET_TILES {
+                            runs.push((at(line, s), at(line, i - 1)));
+                        }
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        runs
+    };
+    (
+        runs(height, width, &|y, x| IVec2::new(x, y)),
+        runs(width, height, &|x, y| IVec2::new(x, y)),
+    )
+}
+
+#[derive(Component)]
+pub struct AddressText {}
+
+pub fn setup_address_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font,
+                font_size: constants.ui.font_size * 0.5,
+                color: constants.ui.font_color,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(constants.ui.fps_text_padding),
+            top: Val::Px(constants.ui.fps_text_padding),
+            ..default()
+        }),
+        AddressText {},
+    ));
+}
+
+/// Shows the district each player is in and where their fare is
+pub fn update_address_ui(
+    addresses: Option<Res<AddressBook>>,
+    mission_state: Res<MissionState>,
+    car_q: Query<(&Player, &Transform, Option<&Ride>), With<Car>>,
+    mut ui_q: Query<&mut Text, With<AddressText>>,
+) {
+    let Some(addresses) = addresses else {
+        return;
+    };
+    let mut players: Vec<_> = car_q.iter().collect();
+    players.sort_by_key(|(player, ..)| player.0);
+    let split = players.len() > 1;
+
+    let mut lines = Vec::new();
+    for (player, car, ride) in players {
+        let district = addresses.district(car.translation.xy()).unwrap_or_default();
+        lines.push(if split {
+            format!("P{} {district}", player.0 + 1)
+        } else {
+            district.to_string()
+        });
+
+        let target = match ride {
+            Some(ride) => ride.dropoff.map(|dropoff| ("Drop off", dropoff)),
+            None => mission_state
+                .current_target
+                .map(|pickup| ("Pick up", pickup)),
+        };
+        let target = target.and_then(|(verb, target)| Some((verb, addresses.address(target)?)));
+        if let Some((verb, target)) = target {
+            lines.push(format!("{verb} at {target}"));
+        }
+    }
+    let label = lines.join("\n");
+    for mut text in &mut ui_q {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::load_map;
+
+    #[test]
+    fn shipped_streets_run_along_roads() {
+        let addresses: AddressBook = serde_json::from_str(LEVEL_ADDRESSES).unwrap();
+        assert!(!addresses.districts.is_empty());
+        assert!(!addresses.streets.is_empty());
+        let tiles = load_map();
+        let tile_at = |tile: IVec2| tiles.get(tile.y as usize)?.get(tile.x as usize).copied();
+        for street in &addresses.streets {
+            for end in [street.from, street.to] {
+                assert!(
+                    tile_at(end).is_some_and(|tp| tp.is_road()),
+                    "{} ends at {end} off the road",
+                    street.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn positions_get_the_closest_address() {
+        use TileType::{Grass, Road};
+        // A street along the bottom row and an avenue up the left column
+        let mut tiles = vec![vec![Grass; 6]; 6];
+        tiles[0].fill(Road);
+        for row in tiles.iter_mut() {
+            row[0] = Road;
+        }
+        let addresses = AddressBook::generate(&tiles, 0);
+        assert_eq!(addresses.streets.len(), 2);
+
+        // Left of the street looking east is north
+        let address = addresses.address(Vec2::new(4., 0.4) * TILE_SIZE).unwrap();
+        assert_eq!(address.street, "Tango Street");
+        assert_eq!(address.number, 9);
+        let across = addresses.address(Vec2::new(4., -0.4) * TILE_SIZE).unwrap();
+        assert_eq!(across.number, 10);
+
+        let address = addresses.address(Vec2::new(0.3, 5.) * TILE_SIZE).unwrap();
+        assert_eq!(address.street, "Station Avenue");
+        assert_eq!(address.district.as_deref(), Some("Riverside"));
+    }
+}
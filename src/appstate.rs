@@ -8,12 +8,14 @@ use bevy_tweening::EaseFunction;
 pub enum AppState {
     #[default]
     Splash,
+    /// Picking the game mode
+    Menu,
     Game,
 }
 
 impl AppState {
     pub fn splash_screen() -> SplashPlugin<AppState> {
-        SplashPlugin::new(AppState::Splash, AppState::Game)
+        SplashPlugin::new(AppState::Splash, AppState::Menu)
             .skipable()
             .add_screen(SplashScreen {
                 brands: vec![SplashItem {
@@ -1,13 +1,27 @@
+//! Texture atlases described by `atlas_manifest.toml`, rebuilt whenever the manifest changes
+
+use std::fmt;
+
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
 use bevy::prelude::*;
-use bevy::utils::HashMap;
+use bevy::utils::{BoxedFuture, HashMap};
 use serde::Deserialize;
 
-static MANIFEST: &str = include_str!(concat!(
-    env!("CARGO_MANIFEST_DIR"),
-    "/assets/atlas_manifest.toml"
-));
+pub const MANIFEST_PATH: &str = "atlas_manifest.toml";
+
+pub struct AtlasPlugin;
+
+impl Plugin for AtlasPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<AtlasManifest>()
+            .init_asset_loader::<AtlasManifestLoader>()
+            .add_systems(Startup, setup_atlases)
+            .add_systems(Update, build_atlases);
+    }
+}
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct AtlasDesc {
     path: String,
     size: Vec2,
@@ -15,12 +29,27 @@ struct AtlasDesc {
     columns: usize,
     #[serde(default = "one_usize")]
     rows: usize,
+    /// Atlas index for each layer from the bottom up, -1 leaves the layer empty
     #[serde(default = "parallax_default")]
     parallax: Vec<i32>,
     #[serde(default = "parallax_z_default")]
     parallax_z: f32,
     #[serde(default)]
     parallax_invert: bool,
+    #[serde(default)]
+    animation: Option<AnimationDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDesc {
+    /// Offsets added to the atlas index of every layer, one for each frame
+    frames: Vec<usize>,
+    /// Frames per second for layers not listed in `layer_fps`
+    fps: f32,
+    /// Frames per second of each layer from the bottom up, so layers can animate at different rates
+    #[serde(default)]
+    layer_fps: Vec<f32>,
 }
 
 fn parallax_default() -> Vec<i32> {
@@ -34,55 +63,424 @@ fn parallax_z_default() -> f32 {
     1.
 }
 
+impl AtlasDesc {
+    fn total(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Atlas index of layer `layer`, `None` if the layer is empty
+    fn layer_index(&self, layer: usize) -> Option<usize> {
+        let l: usize = self.parallax[layer].try_into().ok()?;
+        if self.parallax_invert {
+            self.total().checked_sub(l + 1)
+        } else {
+            Some(l)
+        }
+    }
+
+    /// Every non-empty layer from the bottom up
+    fn layers(&self) -> Vec<LayerInfo> {
+        (0..self.parallax.len())
+            .filter_map(|i| {
+                let index = self.layer_index(i)?;
+                let animation = self.animation.as_ref().map(|anim| {
+                    AtlasAnimation::new(
+                        anim.frames.iter().map(|offset| index + offset).collect(),
+                        anim.layer_fps.get(i).copied().unwrap_or(anim.fps),
+                    )
+                });
+                Some(LayerInfo {
+                    index,
+                    height: i as f32 * self.parallax_z,
+                    animation,
+                })
+            })
+            .collect()
+    }
+
+    fn validate(&self, name: &str, errors: &mut Vec<AtlasError>) {
+        for (layer, &index) in self.parallax.iter().enumerate() {
+            if index < -1 {
+                errors.push(AtlasError::NegativeIndex {
+                    atlas: name.to_string(),
+                    layer,
+                    index,
+                });
+            } else if index >= 0 && index as usize >= self.total() {
+                errors.push(AtlasError::IndexOutOfBounds {
+                    atlas: name.to_string(),
+                    layer,
+                    index,
+                    total: self.total(),
+                });
+            }
+        }
+
+        let Some(animation) = &self.animation else {
+            return;
+        };
+        let bad_animation = |reason| AtlasError::BadAnimation {
+            atlas: name.to_string(),
+            reason,
+        };
+        if animation.frames.is_empty() {
+            errors.push(bad_animation("there are no frames"));
+        }
+        if animation.fps <= 0. || animation.layer_fps.iter().any(|fps| *fps <= 0.) {
+            errors.push(bad_animation("frame rates must be positive"));
+        }
+        if animation.layer_fps.len() > self.parallax.len() {
+            errors.push(bad_animation("there are more frame rates than layers"));
+        }
+        let last_frame = animation.frames.iter().max().copied().unwrap_or(0);
+        for layer in 0..self.parallax.len() {
+            let Some(index) = self.layer_index(layer) else {
+                continue;
+            };
+            if index < self.total() && index + last_frame >= self.total() {
+                errors.push(AtlasError::IndexOutOfBounds {
+                    atlas: name.to_string(),
+                    layer,
+                    index: (index + last_frame) as i32,
+                    total: self.total(),
+                });
+            }
+        }
+    }
+}
+
+#[derive(bevy::asset::Asset, bevy::reflect::TypePath, Deserialize)]
+#[serde(transparent)]
+pub struct AtlasManifest {
+    atlases: HashMap<String, AtlasDesc>,
+}
+
+impl AtlasManifest {
+    /// Parses the manifest and checks every layer index fits in its atlas
+    ///
+    /// Doesn't check that the images exist, as that depends on where assets are read from.
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let manifest: AtlasManifest = toml::from_str(text)?;
+        let mut errors = Vec::new();
+        for (name, desc) in &manifest.atlases {
+            desc.validate(name, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(manifest)
+        } else {
+            Err(ManifestError::Invalid(errors))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    /// Only -1 is allowed as a negative index, for an empty layer
+    NegativeIndex {
+        atlas: String,
+        layer: usize,
+        index: i32,
+    },
+    IndexOutOfBounds {
+        atlas: String,
+        layer: usize,
+        index: i32,
+        total: usize,
+    },
+    MissingFile {
+        atlas: String,
+        path: String,
+    },
+    BadAnimation {
+        atlas: String,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasError::NegativeIndex {
+                atlas,
+                layer,
+                index,
+            } => write!(f, "[{atlas}] layer {layer} has negative index {index}"),
+            AtlasError::IndexOutOfBounds {
+                atlas,
+                layer,
+                index,
+                total,
+            } => write!(
+                f,
+                "[{atlas}] layer {layer} has index {index} but the atlas only has {total} images"
+            ),
+            AtlasError::MissingFile { atlas, path } => {
+                write!(f, "[{atlas}] image {path} does not exist")
+            }
+            AtlasError::BadAnimation { atlas, reason } => {
+                write!(f, "[{atlas}] invalid animation, {reason}")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(Vec<AtlasError>),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Io(e) => write!(f, "could not read {MANIFEST_PATH}: {e}"),
+            ManifestError::Parse(e) => write!(f, "failed parsing {MANIFEST_PATH}: {e}"),
+            ManifestError::Invalid(errors) => {
+                write!(f, "invalid {MANIFEST_PATH}:")?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl From<std::io::Error> for ManifestError {
+    fn from(e: std::io::Error) -> Self {
+        ManifestError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ManifestError {
+    fn from(e: toml::de::Error) -> Self {
+        ManifestError::Parse(e)
+    }
+}
+
+#[derive(Default)]
+pub struct AtlasManifestLoader;
+
+impl AssetLoader for AtlasManifestLoader {
+    type Asset = AtlasManifest;
+    type Settings = ();
+    type Error = ManifestError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<AtlasManifest, ManifestError>> {
+        Box::pin(async move {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).await?;
+            let manifest = AtlasManifest::parse(&text)?;
+
+            // Reading the images also makes the manifest reload when one of them changes
+            let mut errors = Vec::new();
+            for (name, desc) in &manifest.atlases {
+                if load_context
+                    .read_asset_bytes(desc.path.as_str())
+                    .await
+                    .is_err()
+                {
+                    errors.push(AtlasError::MissingFile {
+                        atlas: name.clone(),
+                        path: desc.path.clone(),
+                    });
+                }
+            }
+            if errors.is_empty() {
+                Ok(manifest)
+            } else {
+                Err(ManifestError::Invalid(errors))
+            }
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["toml"]
+    }
+}
+
+pub struct LayerInfo {
+    /// Atlas index of the layer, or its first frame if animated
+    pub index: usize,
+    pub height: f32,
+    pub animation: Option<AtlasAnimation>,
+}
+
 pub struct AtlasInfo {
     pub size: Vec2,
     pub atlas: Handle<TextureAtlas>,
-    pub parallax: Vec<(usize, f32)>,
+    pub parallax: Vec<LayerInfo>,
+    /// Height between consecutive layers
+    pub parallax_z: f32,
+    /// Number of sprites in the atlas
+    pub sprites: usize,
+}
+
+impl AtlasInfo {
+    /// Layers for atlas indices chosen at runtime instead of the manifest's `parallax`, from the
+    /// bottom up. Unlike the manifest, indices are never inverted, and -1 leaves the layer empty
+    pub fn stack_layers(&self, stack: &[i32]) -> Vec<LayerInfo> {
+        stack
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &index)| {
+                let index: usize = index.try_into().ok().filter(|&i| i < self.sprites)?;
+                Some(LayerInfo {
+                    index,
+                    height: i as f32 * self.parallax_z,
+                    animation: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Cycles the atlas index of a sprite through `frames`
+#[derive(Component, Clone, Debug)]
+pub struct AtlasAnimation {
+    /// Atlas index of each frame
+    pub frames: Vec<usize>,
+    pub fps: f32,
+    /// Seconds since the first frame
+    pub time: f32,
 }
 
+impl AtlasAnimation {
+    pub fn new(frames: Vec<usize>, fps: f32) -> Self {
+        Self {
+            frames,
+            fps,
+            time: 0.,
+        }
+    }
+
+    pub fn current_frame(&self) -> usize {
+        let frame = (self.time * self.fps) as usize % self.frames.len().max(1);
+        self.frames.get(frame).copied().unwrap_or_default()
+    }
+}
+
+/// Atlases by their name in the manifest, replaced every time the manifest is reloaded
 #[derive(Resource)]
 pub struct Atlases {
     pub by_name: HashMap<String, AtlasInfo>,
 }
 
-pub fn setup_atlases(
+#[derive(Resource)]
+pub struct AtlasManifestHandle(Handle<AtlasManifest>);
+
+pub fn setup_atlases(mut commands: Commands, server: Res<AssetServer>) {
+    commands.insert_resource(AtlasManifestHandle(server.load(MANIFEST_PATH)));
+}
+
+pub fn build_atlases(
     mut commands: Commands,
+    mut events: EventReader<AssetEvent<AtlasManifest>>,
     server: Res<AssetServer>,
+    manifest_handle: Res<AtlasManifestHandle>,
+    manifests: Res<Assets<AtlasManifest>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
 ) {
-    let atlases: HashMap<String, AtlasDesc> = match toml::from_str(MANIFEST) {
-        Ok(atlases) => atlases,
-        Err(e) => {
-            error!("failed parsing atlas_manifest.toml: {e}");
-            return;
+    let changed = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == manifest_handle.0.id()
         }
+        _ => false,
+    });
+    if !changed {
+        return;
+    }
+    let Some(manifest) = manifests.get(&manifest_handle.0) else {
+        return;
     };
-    let atlases = atlases
-        .into_iter()
+
+    let atlases = manifest
+        .atlases
+        .iter()
         .map(|(k, v)| {
-            let texture = server.load(v.path);
+            let texture = server.load(v.path.clone());
             let atlas = TextureAtlas::from_grid(texture, v.size, v.columns, v.rows, None, None);
-            let total = atlas.textures.len();
             let handle = texture_atlases.add(atlas);
-            let layers = v
-                .parallax
-                .into_iter()
-                .enumerate()
-                .filter_map(|(i, l)| {
-                    let Ok(l) = l.try_into() else {
-                        return None;
-                    };
-                    let index = if v.parallax_invert { total - l - 1 } else { l };
-                    Some((index, i as f32 * v.parallax_z))
-                })
-                .collect();
             let info = AtlasInfo {
                 size: v.size,
                 atlas: handle,
-                parallax: layers,
+                parallax: v.layers(),
+                parallax_z: v.parallax_z,
+                sprites: v.total(),
             };
-            (k, info)
+            (k.clone(), info)
         })
         .collect();
+    info!(
+        "loaded {} atlases from {MANIFEST_PATH}",
+        manifest.atlases.len()
+    );
     commands.insert_resource(Atlases { by_name: atlases });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_manifest_is_valid() {
+        let text = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/atlas_manifest.toml"
+        ));
+        let manifest = AtlasManifest::parse(text).unwrap();
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        for (name, desc) in &manifest.atlases {
+            assert!(assets.join(&desc.path).exists(), "[{name}] {}", desc.path);
+        }
+    }
+
+    #[test]
+    fn invalid_manifests_are_rejected() {
+        let typo = "[pole]\npath = \"pole/pole.png\"\nsize = [64, 32]\nclumns = 1\n";
+        assert!(matches!(
+            AtlasManifest::parse(typo),
+            Err(ManifestError::Parse(_))
+        ));
+
+        let out_of_bounds =
+            "[car]\npath = \"car/car.png\"\nsize = [64, 64]\nrows = 4\nparallax = [-1, 4, -2]\n";
+        let Err(ManifestError::Invalid(errors)) = AtlasManifest::parse(out_of_bounds) else {
+            panic!("out of bounds indices were accepted");
+        };
+        assert!(matches!(
+            errors[..],
+            [
+                AtlasError::IndexOutOfBounds { layer: 1, .. },
+                AtlasError::NegativeIndex { layer: 2, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn animation_frames_are_offset_per_layer() {
+        let text = "[signal]\npath = \"signal.png\"\nsize = [64, 64]\ncolumns = 2\nrows = 2\n\
+                    parallax = [0, -1, 2]\nanimation = { frames = [0, 1], fps = 2.0, layer_fps = [4.0] }\n";
+        let manifest = AtlasManifest::parse(text).unwrap();
+        let layers = manifest.atlases["signal"].layers();
+        let animations: Vec<_> = layers
+            .iter()
+            .map(|l| l.animation.as_ref().map(|a| (a.frames.clone(), a.fps)))
+            .collect();
+        assert_eq!(
+            animations,
+            vec![Some((vec![0, 1], 4.)), Some((vec![2, 3], 2.))]
+        );
+
+        let too_many_frames = text.replace("frames = [0, 1]", "frames = [0, 2]");
+        assert!(AtlasManifest::parse(&too_many_frames).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Engine, tire and collision sounds and event chimes
+
+use bevy::{audio::Volume, prelude::*};
+use bevy_rapier2d::{prelude::*, rapier::geometry::CollisionEventFlags};
+
+use crate::{
+    car::{Car, Player, Tire},
+    constants::Constants,
+    missions::MissionEvent,
+    plugins::GameSet,
+    zones::ZoneEvent,
+};
+
+/// The engine pitch drops every time the car "shifts" up a gear
+const ENGINE_GEARS: f32 = 4.;
+/// Minimum seconds between two collision sounds
+const THUD_COOLDOWN: f32 = 0.25;
+
+pub struct GameAudioPlugin;
+
+impl Plugin for GameAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_audio).add_systems(
+            Update,
+            (
+                update_engine_sound,
+                update_screech,
+                play_collision_thuds,
+                play_chimes,
+            )
+                .in_set(GameSet::Audio),
+        );
+    }
+}
+
+#[derive(Resource)]
+pub struct SoundEffects {
+    pub thud: Handle<AudioSource>,
+    pub chime: Handle<AudioSource>,
+}
+
+#[derive(Component)]
+pub struct EngineSound;
+
+#[derive(Component)]
+pub struct ScreechSound;
+
+/// Looping sound that starts muted, for sounds whose volume is driven by a system
+pub fn silent_loop<T: Asset + Decodable>(source: Handle<T>) -> AudioSourceBundle<T> {
+    AudioSourceBundle {
+        source,
+        settings: PlaybackSettings::LOOP.with_volume(Volume::new_relative(0.)),
+    }
+}
+
+pub fn play_sfx(commands: &mut Commands, source: Handle<AudioSource>, volume: f32, speed: f32) {
+    commands.spawn(AudioBundle {
+        source,
+        settings: PlaybackSettings::DESPAWN
+            .with_volume(Volume::new_relative(volume))
+            .with_speed(speed),
+    });
+}
+
+pub fn setup_audio(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        silent_loop::<AudioSource>(asset_server.load("audio/sfx/engine.wav")),
+        EngineSound,
+    ));
+    commands.spawn((
+        silent_loop::<AudioSource>(asset_server.load("audio/sfx/screech.wav")),
+        ScreechSound,
+    ));
+    commands.insert_resource(SoundEffects {
+        thud: asset_server.load("audio/sfx/thud.wav"),
+        chime: asset_server.load("audio/sfx/chime.wav"),
+    });
+}
+
+pub fn update_engine_sound(
+    constants: Res<Constants>,
+    car_q: Query<&Velocity, (With<Car>, With<Player>)>,
+    sink_q: Query<&AudioSink, With<EngineSound>>,
+) {
+    let Ok(sink) = sink_q.get_single() else {
+        return;
+    };
+    // The fastest player is heard over the others on a split screen
+    let speed = car_q
+        .iter()
+        .map(|velocity| velocity.linvel.length())
+        .fold(0., f32::max);
+    let speed = (speed / constants.car.max_speed).clamp(0., 1.);
+
+    // Fake RPM climbing through each gear and dropping back on every shift
+    let rpm = if speed < 1. {
+        (speed * ENGINE_GEARS).fract()
+    } else {
+        1.
+    };
+
+    sink.set_speed(0.6 + 0.6 * rpm + 0.4 * speed);
+    sink.set_volume(
+        constants.audio.master_volume * constants.audio.engine_volume * (0.4 + 0.6 * speed),
+    );
+}
+
+pub fn update_screech(
+    constants: Res<Constants>,
+    tire_q: Query<(&Tire, &Parent)>,
+    player_q: Query<(), (With<Car>, With<Player>)>,
+    sink_q: Query<&AudioSink, With<ScreechSound>>,
+) {
+    let Ok(sink) = sink_q.get_single() else {
+        return;
+    };
+    let slip = tire_q
+        .iter()
+        .filter(|(_, parent)| player_q.contains(parent.get()))
+        .map(|(tire, _)| tire.slip.abs())
+        .fold(0., f32::max);
+
+    let threshold = constants.audio.screech_slip;
+    let amount = ((slip - threshold) / threshold).clamp(0., 1.);
+    sink.set_volume(constants.audio.master_volume * constants.audio.sfx_volume * amount);
+}
+
+pub fn play_collision_thuds(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut cooldown: Local<f32>,
+    time: Res<Time>,
+    constants: Res<Constants>,
+    sounds: Res<SoundEffects>,
+    player_q: Query<&Velocity, (With<Car>, With<Player>)>,
+) {
+    *cooldown -= time.delta_seconds();
+
+    for event in collision_events.read() {
+        let CollisionEvent::Started(c1, c2, flags) = *event else {
+            continue;
+        };
+        if flags.contains(CollisionEventFlags::SENSOR) || *cooldown > 0. {
+            continue;
+        }
+        let Ok(velocity) = player_q.get(c1).or_else(|_| player_q.get(c2)) else {
+            continue;
+        };
+
+        *cooldown = THUD_COOLDOWN;
+        let impact = (velocity.linvel.length() / constants.car.max_speed).clamp(0.2, 1.);
+        let volume = constants.audio.master_volume * constants.audio.sfx_volume * impact;
+        play_sfx(&mut commands, sounds.thud.clone(), volume, 1.);
+    }
+}
+
+pub fn play_chimes(
+    mut commands: Commands,
+    mut mission_events: EventReader<MissionEvent>,
+    mut zone_events: EventReader<ZoneEvent>,
+    constants: Res<Constants>,
+    sounds: Res<SoundEffects>,
+) {
+    let volume = constants.audio.master_volume * constants.audio.sfx_volume;
+    // The same chime is pitched differently for each event
+    let mission_pitches = mission_events.read().map(|event| match event {
+        MissionEvent::Started(..) => 1.,
+        MissionEvent::Finished(..) => 1.25,
+    });
+    let zone_pitches = zone_events.read().map(|event| match event {
+        ZoneEvent::Fined { .. } => 0.5,
+        ZoneEvent::CheckpointReached(_) => 1.5,
+    });
+    for pitch in mission_pitches.chain(zone_pitches) {
+        play_sfx(&mut commands, sounds.chime.clone(), volume, pitch);
+    }
+}
@@ -0,0 +1,178 @@
+//! Road pieces chosen by the rules in `road.autotile.json` from which neighbours are also roads
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    constants::TILE_SIZE,
+    piece::{make_lamppost, spawn_as_child, Piece, PieceMeta},
+};
+
+static RULES: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/tilemap/road.autotile.json"
+));
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Corner {
+    Ne,
+    Se,
+    Sw,
+    Nw,
+}
+
+#[derive(Deserialize)]
+struct PieceDesc {
+    atlas: String,
+    /// Position in thirds of a tile from the center, each between -1 and 1
+    #[serde(default)]
+    cell: [i32; 2],
+    #[serde(default)]
+    z: f32,
+    /// Counterclockwise quarter turns
+    #[serde(default)]
+    turns: i32,
+    /// Lights up at night and has a collider
+    #[serde(default)]
+    lamppost: bool,
+    /// Only on tiles where `x + y` has this parity, so lampposts alternate sides
+    #[serde(default)]
+    parity: Option<i32>,
+    /// Only when this diagonal neighbour isn't a road, for sidewalk corners
+    #[serde(default)]
+    open_corner: Option<Corner>,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    name: String,
+    /// Whether the north, east, south and west neighbours are roads, `1`, `0` or `*` for either.
+    /// Also matches the pattern rotated by quarter turns, with the pieces rotated to match
+    sides: String,
+    pieces: Vec<PieceDesc>,
+}
+
+impl Rule {
+    fn matches(&self, sides: [bool; 4]) -> bool {
+        self.sides.len() == 4
+            && self.sides.chars().zip(sides).all(|(c, side)| match c {
+                '1' => side,
+                '0' => !side,
+                _ => true,
+            })
+    }
+}
+
+/// Road neighbours of a tile, in the order north, east, south and west for `sides`, and
+/// north-east, south-east, south-west and north-west for `corners`
+#[derive(Debug, Clone, Copy)]
+pub struct RoadNeighbors {
+    pub sides: [bool; 4],
+    pub corners: [bool; 4],
+}
+
+impl RoadNeighbors {
+    /// The neighbours as seen after turning the tile clockwise `count` quarter turns
+    fn rotate_cw(&self, count: usize) -> Self {
+        let mut rotated = *self;
+        rotated.sides.rotate_right(count % 4);
+        rotated.corners.rotate_right(count % 4);
+        rotated
+    }
+
+    fn corner(&self, corner: Corner) -> bool {
+        self.corners[corner as usize]
+    }
+}
+
+pub struct AutotileRules {
+    rules: Vec<Rule>,
+}
+
+impl AutotileRules {
+    pub fn load() -> Self {
+        let rules = match serde_json::from_str(RULES) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("failed parsing road.autotile.json: {e}");
+                Vec::new()
+            }
+        };
+        Self { rules }
+    }
+
+    /// First rule matching the neighbours, and how many quarter turns it is rotated by
+    fn find(&self, neighbors: &RoadNeighbors) -> Option<(&Rule, usize)> {
+        self.rules.iter().find_map(|rule| {
+            (0..4)
+                .find(|&turns| rule.matches(neighbors.rotate_cw(turns).sides))
+                .map(|turns| (rule, turns))
+        })
+    }
+
+    /// Spawns the pieces of the road tile at `tile`, returns `false` if no rule matched
+    pub fn spawn(&self, cb: &mut ChildBuilder, tile: IVec2, neighbors: &RoadNeighbors) -> bool {
+        let Some((rule, turns)) = self.find(neighbors) else {
+            return false;
+        };
+        let rotated = neighbors.rotate_cw(turns);
+        let parity = (tile.x + tile.y).rem_euclid(2);
+        let rotation = Quat::from_rotation_z(turns as f32 * PI / 2.);
+
+        for piece in &rule.pieces {
+            if piece.parity.is_some_and(|p| p != parity)
+                || piece.open_corner.is_some_and(|c| rotated.corner(c))
+            {
+                continue;
+            }
+            let cell = Vec2::new(piece.cell[0] as f32, piece.cell[1] as f32);
+            let mut transform =
+                Transform::from_translation((cell * TILE_SIZE / 3.).extend(piece.z));
+            transform.rotate_z(piece.turns as f32 * PI / 2.);
+            transform.rotate_around(Vec3::ZERO, rotation);
+
+            let meta = if piece.lamppost {
+                make_lamppost(transform).1
+            } else {
+                PieceMeta::new(transform, None, None)
+            };
+            spawn_as_child(cb, (Piece::new(&piece.atlas), meta));
+        }
+        trace!("road tile {tile} uses rule {}", rule.name);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_road_shape_has_a_rule() {
+        let rules = AutotileRules::load();
+        for mask in 0..16 {
+            let sides = [0, 1, 2, 3].map(|i| mask & (1 << i) != 0);
+            let neighbors = RoadNeighbors {
+                sides,
+                corners: [false; 4],
+            };
+            assert!(rules.find(&neighbors).is_some(), "{sides:?}");
+        }
+    }
+
+    #[test]
+    fn rules_rotate_with_the_neighbours() {
+        let rules = AutotileRules::load();
+        // Only a road to the west, so the dead end is turned a quarter clockwise
+        let neighbors = RoadNeighbors {
+            sides: [false, false, false, true],
+            corners: [false; 4],
+        };
+        let (rule, turns) = rules.find(&neighbors).unwrap();
+        assert_eq!(rule.name, "dead end");
+        assert_eq!(turns, 3);
+    }
+}
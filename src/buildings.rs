@@ -1,27 +1,128 @@
+//! Buildings stacked from the floors, windows and roofs in `building2.palette.json`
+
 use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy::utils::HashSet;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
-use crate::parallax::{ParallaxImages, ParallaxSprite};
+use crate::{
+    constants::TILE_SIZE,
+    parallax::{ParallaxImages, ParallaxSprite},
+    utility::Rng,
+};
 
-// TODO correct scaling
-const BUILDING_SCALE: f32 = 6.0;
+static PALETTE: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/building2/building2.palette.json"
+));
 
-#[derive(Component)]
-pub enum Building {
-    Building1,
+/// Rows of the building atlases that buildings are composed of, each part a list of atlas
+/// indices from the bottom up
+#[derive(Deserialize)]
+pub struct BuildingPalette {
+    atlas: String,
+    /// Atlas of the flat roof drawn on top of the walls
+    roof_atlas: String,
+    min_floors: usize,
+    max_floors: usize,
+    grounds: Vec<Vec<i32>>,
+    floors: Vec<Vec<i32>>,
+    /// Top of the walls, above the last floor
+    roofs: Vec<Vec<i32>>,
+    ceiling: Vec<i32>,
+    tints: Vec<[f32; 3]>,
+}
+
+impl BuildingPalette {
+    pub fn load() -> Self {
+        match serde_json::from_str(PALETTE) {
+            Ok(palette) => palette,
+            Err(e) => {
+                error!("failed parsing building2.palette.json: {e}");
+                Self::fallback()
+            }
+        }
+    }
+
+    /// The original hand made building
+    fn fallback() -> Self {
+        Self {
+            atlas: "building2".into(),
+            roof_atlas: "ceiling2".into(),
+            min_floors: 6,
+            max_floors: 6,
+            grounds: vec![vec![11, 10, 10]],
+            floors: vec![vec![8, 8, 9, 9, 9, 9, 8, 8, 7, 7, 6, 6]],
+            roofs: vec![vec![5, 5, 4, 4, 3, 2, 2, 2, 2, 1, 1, 2, 2, 0, 0, 0]],
+            ceiling: vec![0, 1, 2],
+            tints: vec![[1., 1., 1.]],
+        }
+    }
+}
+
+/// A generated building, the same for the same seed
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Building {
+    pub floors: usize,
+    ground: usize,
+    floor: usize,
+    roof: usize,
+    tint: usize,
+}
+
+/// Random numbers seeded by the tile position, so every tile always gets the same building
+fn tile_rng(tile: IVec2) -> Rng {
+    Rng::new(tile.x as u64 ^ (tile.y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f))
 }
 
 impl Building {
-    fn get_parallax_images(&self) -> ParallaxImages {
-        use Building::*;
-        let sprite = Sprite {
-            custom_size: Some(Vec2::new(192. * BUILDING_SCALE, 16. * BUILDING_SCALE)),
+    pub fn generate(palette: &BuildingPalette, tile: IVec2) -> Self {
+        let mut rng = tile_rng(tile);
+        let mut random = |n| rng.below(n);
+        let max_floors = palette.max_floors.max(palette.min_floors);
+        Self {
+            floors: palette.min_floors + random(max_floors - palette.min_floors + 1),
+            ground: random(palette.grounds.len()),
+            floor: random(palette.floors.len()),
+            roof: random(palette.roofs.len()),
+            tint: random(palette.tints.len()),
+        }
+    }
+
+    /// Atlas indices of the walls from the bottom up
+    fn wall_stack(&self, palette: &BuildingPalette) -> Vec<i32> {
+        let part = |parts: &[Vec<i32>], i: usize| parts.get(i).cloned().unwrap_or_default();
+        let mut stack = part(&palette.grounds, self.ground);
+        for _ in 0..self.floors {
+            stack.extend(part(&palette.floors, self.floor));
+        }
+        stack.extend(part(&palette.roofs, self.roof));
+        stack
+    }
+
+    fn sprite(&self, palette: &BuildingPalette, anchor: Anchor) -> Sprite {
+        let [r, g, b] = palette.tints.get(self.tint).copied().unwrap_or([1.; 3]);
+        Sprite {
+            color: Color::rgb(r, g, b),
+            anchor,
             ..Default::default()
-        };
-        match *self {
-            Building1 => ParallaxImages::new("building2", sprite.clone()),
         }
     }
+
+    /// Walls facing one side, anchored so the stack stands on that side of the tile
+    pub fn walls(&self, palette: &BuildingPalette, anchor: Anchor) -> ParallaxImages {
+        ParallaxImages::new(&palette.atlas, self.sprite(palette, anchor))
+            .with_stack(self.wall_stack(palette))
+    }
+
+    /// Roof covering the tile, on top of the walls
+    pub fn roof(&self, palette: &BuildingPalette) -> ParallaxImages {
+        let mut stack = vec![-1; self.wall_stack(palette).len()];
+        stack.extend(&palette.ceiling);
+        ParallaxImages::new(&palette.roof_atlas, self.sprite(palette, Anchor::Center))
+            .with_stack(stack)
+    }
 }
 
 #[derive(Bundle)]
@@ -31,23 +132,163 @@ pub struct BuildingBundle {
 }
 
 impl BuildingBundle {
-    pub fn spawn(commands: &mut Commands) {
-        let building = BuildingBundle {
-            building: Building::Building1,
+    pub fn new(building: Building, images: ParallaxImages, transform: Transform) -> Self {
+        Self {
+            building,
             sprite: ParallaxSprite {
-                images: Building::Building1.get_parallax_images(),
-                // ..Default::default()
-                visibility: VisibilityBundle::default(),
-                transform: TransformBundle::default(),
+                transform: transform.into(),
+                visibility: Default::default(),
+                images,
             },
-        };
-
-        commands
-            .spawn(building)
-            .insert(RigidBody::Fixed)
-            .insert(Collider::cuboid(
-                192.0 / 2.0 * BUILDING_SCALE,
-                4.0 / 2.0 * BUILDING_SCALE,
-            ));
+        }
+    }
+}
+
+/// Contiguous building tiles sharing one style and one collider
+#[derive(Component, Debug)]
+pub struct BuildingBlock {
+    pub building: Building,
+    /// Map tiles covered by the block
+    pub tiles: Vec<IVec2>,
+}
+
+impl BuildingBlock {
+    /// Splits building tiles into 4-connected blocks, styled by their bottom left tile
+    pub fn group(palette: &BuildingPalette, tiles: &[IVec2]) -> Vec<Self> {
+        let mut remaining: HashSet<IVec2> = tiles.iter().copied().collect();
+        let mut sorted = tiles.to_vec();
+        sorted.sort_by_key(|t| (t.y, t.x));
+
+        let mut blocks = Vec::new();
+        for start in sorted {
+            if !remaining.remove(&start) {
+                continue;
+            }
+            let mut block = vec![start];
+            let mut open = vec![start];
+            while let Some(tile) = open.pop() {
+                for side in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                    if remaining.remove(&(tile + side)) {
+                        block.push(tile + side);
+                        open.push(tile + side);
+                    }
+                }
+            }
+            blocks.push(Self {
+                building: Building::generate(palette, start),
+                tiles: block,
+            });
+        }
+        blocks
+    }
+
+    /// Rectangles covering the block as few as possible, as inclusive min and max tiles
+    fn rects(&self) -> Vec<(IVec2, IVec2)> {
+        let mut tiles = self.tiles.clone();
+        tiles.sort_by_key(|t| (t.y, t.x));
+
+        // Runs of consecutive tiles in each row ...
+        let mut runs: Vec<(IVec2, IVec2)> = Vec::new();
+        for tile in tiles {
+            match runs.last_mut() {
+                Some((_, max)) if *max + IVec2::X == tile => *max = tile,
+                _ => runs.push((tile, tile)),
+            }
+        }
+        // ... grown upwards over identical runs in the rows above
+        let mut rects: Vec<(IVec2, IVec2)> = Vec::new();
+        for (min, max) in runs {
+            let below = rects
+                .iter_mut()
+                .find(|(rmin, rmax)| rmin.x == min.x && rmax.x == max.x && rmax.y + 1 == min.y);
+            match below {
+                Some((_, rmax)) => rmax.y = max.y,
+                None => rects.push((min, max)),
+            }
+        }
+        rects
+    }
+
+    /// One collider for the whole block, placed at the origin of the map
+    pub fn collider(&self) -> Collider {
+        let shapes = self
+            .rects()
+            .into_iter()
+            .map(|(min, max)| {
+                let center = (min + max).as_vec2() / 2. * TILE_SIZE;
+                let half_size = (max - min + IVec2::ONE).as_vec2() / 2. * TILE_SIZE;
+                (center, 0., Collider::cuboid(half_size.x, half_size.y))
+            })
+            .collect();
+        Collider::compound(shapes)
+    }
+}
+
+/// Spawns the roof of a building tile, and walls on the sides in `outline`. The sides are south,
+/// east, north and west, the order the walls are rotated to
+pub fn spawn_building(
+    cb: &mut ChildBuilder,
+    palette: &BuildingPalette,
+    building: &Building,
+    outline: [bool; 4],
+) {
+    for (rot_count, _) in outline.iter().enumerate().filter(|(_, outer)| **outer) {
+        let angle = rot_count as f32 * std::f32::consts::PI / 2.;
+        let transform = Transform::from_rotation(Quat::from_rotation_z(angle));
+        let walls = building.walls(palette, Anchor::Custom(Vec2::new(0., 6.)));
+        cb.spawn(BuildingBundle::new(building.clone(), walls, transform));
+    }
+    cb.spawn(BuildingBundle::new(
+        building.clone(),
+        building.roof(palette),
+        Transform::default(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_palette_fits_the_atlases() {
+        let palette: BuildingPalette = serde_json::from_str(PALETTE).unwrap();
+        // building2 has 12 rows and ceiling2 4
+        let parts = [&palette.grounds, &palette.floors, &palette.roofs];
+        for index in parts.into_iter().flatten().flatten() {
+            assert!((0..12).contains(index), "{index}");
+        }
+        assert!(palette.ceiling.iter().all(|i| (0..4).contains(i)));
+        assert!(palette.min_floors <= palette.max_floors);
+    }
+
+    #[test]
+    fn buildings_are_seeded_by_tile() {
+        let palette = BuildingPalette::load();
+        let at = |x, y| Building::generate(&palette, IVec2::new(x, y));
+        assert_eq!(at(3, 4), at(3, 4));
+        let buildings: Vec<Building> = (0..16).map(|x| at(x, 0)).collect();
+        assert!(buildings.iter().any(|b| *b != buildings[0]));
+        assert!(buildings
+            .iter()
+            .all(|b| (palette.min_floors..=palette.max_floors).contains(&b.floors)));
+    }
+
+    #[test]
+    fn contiguous_tiles_form_blocks() {
+        let palette = BuildingPalette::load();
+        // An L shaped block and a separate tile
+        let tiles =
+            [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2), (5, 5)].map(|(x, y)| IVec2::new(x, y));
+        let blocks = BuildingBlock::group(&palette, &tiles);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].tiles.len(), 6);
+
+        let rects = blocks[0].rects();
+        let area: i32 = rects
+            .iter()
+            .map(|(min, max)| (max.x - min.x + 1) * (max.y - min.y + 1))
+            .sum();
+        assert_eq!(area, 6);
+        assert_eq!(rects.len(), 3);
     }
 }
@@ -0,0 +1,198 @@
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{camera::Viewport, view::RenderLayers},
+    window::PrimaryWindow,
+};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    car::{Car, Player},
+    constants::Constants,
+    plugins::GameSet,
+};
+
+/// Draws the HUD over the split screen views
+const HUD_CAMERA_ORDER: isize = 100;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_graphics).add_systems(
+            Update,
+            (add_player_cameras, layout_viewports, camera_follow)
+                .chain()
+                .in_set(GameSet::Camera),
+        );
+    }
+}
+
+/// Follows the car of the player with this index
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCamera(pub usize);
+
+/// Renders only the UI, over the whole window, once the screen is split
+#[derive(Component)]
+pub struct HudCamera;
+
+/// Layer of what is drawn only in the view of the `view`th player, e.g. its weather
+pub fn view_layer(view: usize) -> RenderLayers {
+    RenderLayers::layer(view as u8 + 1)
+}
+
+/// Layers the camera of the `view`th player renders, the shared world and its own layer
+fn camera_layers(view: usize) -> RenderLayers {
+    RenderLayers::layer(0).with(view as u8 + 1)
+}
+
+pub fn setup_graphics(mut commands: Commands) {
+    commands.spawn((Camera2dBundle::default(), PlayerCamera(0), camera_layers(0)));
+}
+
+/// Gives every player joining a camera of their own, and moves the HUD out of the first
+/// player's view when the screen gets split
+pub fn add_player_cameras(
+    mut commands: Commands,
+    player_q: Query<(&Player, &Transform)>,
+    camera_q: Query<(Entity, &PlayerCamera)>,
+    hud_q: Query<(), With<HudCamera>>,
+) {
+    for (player, transform) in player_q.iter() {
+        if camera_q.iter().any(|(_, camera)| camera.0 == player.0) {
+            continue;
+        }
+        let mut camera = Camera2dBundle::default();
+        camera.camera.order = player.0 as isize;
+        camera.transform.translation = transform
+            .translation
+            .xy()
+            .extend(camera.transform.translation.z);
+        commands.spawn((
+            camera,
+            PlayerCamera(player.0),
+            camera_layers(player.0),
+            UiCameraConfig { show_ui: false },
+        ));
+    }
+
+    if player_q.iter().count() > 1 && hud_q.is_empty() {
+        for (camera, _) in camera_q.iter() {
+            commands
+                .entity(camera)
+                .insert(UiCameraConfig { show_ui: false });
+        }
+        commands.spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    order: HUD_CAMERA_ORDER,
+                    ..default()
+                },
+                camera_2d: Camera2d {
+                    clear_color: ClearColorConfig::None,
+                },
+                ..default()
+            },
+            RenderLayers::none(),
+            HudCamera,
+        ));
+    }
+}
+
+/// Position and size of the view of the `index`th of `count` players in a window of `size`,
+/// side by side for two players and in a 2x2 grid for more
+pub fn split_view(count: usize, index: usize, size: UVec2) -> (UVec2, UVec2) {
+    let columns = count.clamp(1, 2) as u32;
+    let rows = ((count as u32 + columns - 1) / columns).max(1);
+    let view = UVec2::new(size.x / columns, size.y / rows);
+    let cell = UVec2::new(index as u32 % columns, index as u32 / columns);
+    (cell * view, view)
+}
+
+pub fn layout_viewports(
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    mut camera_q: Query<(&PlayerCamera, &mut Camera)>,
+) {
+    let Ok(window) = window_q.get_single() else {
+        return;
+    };
+    let size = UVec2::new(window.physical_width(), window.physical_height());
+    let count = camera_q.iter().count();
+    for (player, mut camera) in camera_q.iter_mut() {
+        let viewport = (count > 1).then(|| {
+            let (physical_position, physical_size) = split_view(count, player.0, size);
+            Viewport {
+                physical_position,
+                physical_size,
+                ..default()
+            }
+        });
+        let unchanged = match (&camera.viewport, &viewport) {
+            (Some(old), Some(new)) => {
+                old.physical_position == new.physical_position
+                    && old.physical_size == new.physical_size
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            camera.viewport = viewport;
+        }
+    }
+}
+
+pub fn camera_follow(
+    time: Res<Time>,
+    constants: Res<Constants>,
+    car_q: Query<(&Player, &Transform, &Velocity), With<Car>>,
+    mut camera_q: Query<(&PlayerCamera, &mut Transform, &mut OrthographicProjection), Without<Car>>,
+) {
+    // Ease in camera using exponential decay
+    let easing_factor = (-time.delta_seconds() * constants.camera.easing_speed).exp();
+
+    for (player, mut camera_transform, mut camera_projection) in camera_q.iter_mut() {
+        let car = car_q.iter().find(|(car, ..)| car.0 == player.0);
+        let Some((_, car_pos, car_vel)) = car else {
+            continue;
+        };
+        let car_pos = car_pos.translation.xy();
+        let car_vel = car_vel.linvel;
+
+        // The camera target is `lookahead` seconds in front of car
+        let camera_target_pos = car_pos + constants.camera.lookahead * car_vel;
+        let camera_target_scale = (1.
+            + constants.camera.height_speed_factor * car_vel.length() / constants.car.max_speed)
+            * constants.camera.scale;
+        // TODO: Should the height scale with speed? This creates weird "zoom out" effect
+        let camera_target_height = constants.camera.height * camera_target_scale;
+
+        let camera_pos = easing_factor * camera_transform.translation.xy()
+            + (1. - easing_factor) * camera_target_pos;
+        let camera_height = easing_factor * camera_transform.translation.z
+            + (1. - easing_factor) * camera_target_height;
+        let camera_scale =
+            easing_factor * camera_projection.scale + (1. - easing_factor) * camera_target_scale;
+
+        camera_transform.translation = Vec3::new(camera_pos.x, camera_pos.y, camera_height);
+        camera_projection.scale = camera_scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_split_the_window() {
+        let size = UVec2::new(1920, 1080);
+        assert_eq!(split_view(1, 0, size), (UVec2::ZERO, size));
+        assert_eq!(
+            split_view(2, 1, size),
+            (UVec2::new(960, 0), UVec2::new(960, 1080))
+        );
+        assert_eq!(
+            split_view(3, 2, size),
+            (UVec2::new(0, 540), UVec2::new(960, 540))
+        );
+    }
+}
@@ -4,11 +4,110 @@ use bevy_rapier2d::prelude::*;
 use crate::constants::{CAR_COLLIDER_SIZE_PX, CAR_SPRITE_SCALE};
 use crate::{
     constants::Constants,
+    daynight::Headlights,
     parallax::{ParallaxImages, ParallaxSprite},
+    plugins::GameSet,
+    pointer::PointerBundle,
+    tilemap::CityMap,
 };
 
-#[derive(Component)]
-pub struct Player;
+/// Car physics and player controls
+pub struct CarPlugin;
+
+impl Plugin for CarPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TireGrip>()
+            .add_systems(
+                Update,
+                (car_control, update_turn_signals).in_set(GameSet::Input),
+            )
+            .add_systems(FixedUpdate, tire_friction)
+            .add_systems(FixedUpdate, update_tire_forces.after(tire_friction));
+    }
+}
+
+/// Spawns the player cars at the start of the level
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerCount>()
+            .add_systems(Startup, setup_player)
+            .add_systems(Update, join_players.in_set(GameSet::Input));
+    }
+}
+
+/// Distance between the cars of the players at the start
+const PLAYER_SPACING: f32 = 2.5 * CAR_COLLIDER_SIZE_PX.x;
+
+/// A car driven by a player, with the index of the player starting from 0
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Player(pub usize);
+
+/// How many players share the screen
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCount(pub usize);
+
+impl Default for PlayerCount {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// Keys a player drives with
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerInput {
+    pub accelerate: KeyCode,
+    pub brake: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+}
+
+impl PlayerInput {
+    /// Keys of each player in turn: the arrow keys, WASD, IJKL and the numpad
+    pub const MAPS: [PlayerInput; 4] = [
+        PlayerInput {
+            accelerate: KeyCode::Up,
+            brake: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+        },
+        PlayerInput {
+            accelerate: KeyCode::W,
+            brake: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+        },
+        PlayerInput {
+            accelerate: KeyCode::I,
+            brake: KeyCode::K,
+            left: KeyCode::J,
+            right: KeyCode::L,
+        },
+        PlayerInput {
+            accelerate: KeyCode::Numpad8,
+            brake: KeyCode::Numpad5,
+            left: KeyCode::Numpad4,
+            right: KeyCode::Numpad6,
+        },
+    ];
+
+    /// Controls the keys are giving
+    fn controls(&self, keyboard_input: &Input<KeyCode>) -> CarControls {
+        let mut steer = 0.;
+        if keyboard_input.pressed(self.left) {
+            steer += 1.;
+        }
+        if keyboard_input.pressed(self.right) {
+            steer -= 1.;
+        }
+        CarControls {
+            accelerate: keyboard_input.pressed(self.accelerate),
+            brake: keyboard_input.pressed(self.brake),
+            steer,
+        }
+    }
+}
 
 #[derive(Component)]
 pub struct Car {}
@@ -20,6 +119,15 @@ pub struct CarState {
     pub passenger: bool,
 }
 
+/// Pedals and steering of a car not driven by the player, set by its AI each frame
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
+pub struct CarControls {
+    pub accelerate: bool,
+    pub brake: bool,
+    /// Between -1 for full right and 1 for full left
+    pub steer: f32,
+}
+
 #[derive(Bundle)]
 pub struct CarBundle {
     car: Car,
@@ -118,19 +226,48 @@ impl CarBundle {
                 .insert(ColliderDebugColor(Color::rgb(1., 0., 1.)));
 
             // Spawn headlights
-            parent.spawn(ParallaxSprite {
-                images: ParallaxImages::new(
-                    "headlights",
-                    Sprite {
+            parent.spawn((
+                ParallaxSprite {
+                    images: ParallaxImages::new(
+                        "headlights",
+                        Sprite {
+                            ..Default::default()
+                        },
+                    ),
+                    transform: TransformBundle {
+                        local: Transform::from_translation(Vec3::new(
+                            0.,
+                            CAR_SPRITE_SCALE * 32.,
+                            0.,
+                        )),
                         ..Default::default()
                     },
-                ),
-                transform: TransformBundle {
-                    local: Transform::from_translation(Vec3::new(0., CAR_SPRITE_SCALE * 32., 0.)),
-                    ..Default::default()
+                    visibility: Default::default(),
                 },
-                visibility: Default::default(),
-            });
+                Headlights,
+            ));
+
+            // Spawn turn signals
+            for left in [true, false] {
+                parent.spawn((
+                    ParallaxSprite {
+                        images: ParallaxImages::new(
+                            "turn_signal",
+                            Sprite {
+                                custom_size: Some(CAR_SPRITE_SCALE * Vec2::new(64., 64.)),
+                                flip_x: !left,
+                                ..Default::default()
+                            },
+                        ),
+                        transform: TransformBundle::default(),
+                        visibility: VisibilityBundle {
+                            visibility: Visibility::Hidden,
+                            ..Default::default()
+                        },
+                    },
+                    TurnSignal { left },
+                ));
+            }
         });
 
         CarHandle(commands, car)
@@ -148,9 +285,31 @@ impl CarHandle<'_, '_, '_> {
         CarHandle(commands, car)
     }
 
-    pub fn with_player(self) -> Self {
+    /// Colors the car body, e.g. for police cars
+    pub fn with_tint(self, color: Color) -> Self {
+        let CarHandle(commands, car) = self;
+        let sprite = Sprite {
+            color,
+            custom_size: Some(CAR_SPRITE_SCALE * Vec2::new(64., 64.)),
+            ..Default::default()
+        };
+        commands
+            .entity(car)
+            .insert(ParallaxImages::new("car", sprite));
+        CarHandle(commands, car)
+    }
+
+    /// Makes the car the `index`th player's, driven with their keys and followed by an arrow
+    /// to their target
+    pub fn with_player(self, index: usize) -> Self {
         let CarHandle(commands, car) = self;
-        commands.entity(car).insert(Player);
+        let input = PlayerInput::MAPS[index % PlayerInput::MAPS.len()];
+        commands
+            .entity(car)
+            .insert((Player(index), input))
+            .with_children(|parent| {
+                parent.spawn(PointerBundle::new());
+            });
         CarHandle(commands, car)
     }
 
@@ -159,6 +318,35 @@ impl CarHandle<'_, '_, '_> {
     }
 }
 
+pub fn setup_player(mut commands: Commands, map: Option<Res<CityMap>>) {
+    let start = map
+        .and_then(|map| map.start)
+        .unwrap_or(Vec2::new(16_355.2, 10_900.));
+    CarBundle::spawn(&mut commands).with_player(0).at(start);
+}
+
+/// Spawns the cars of players joining after the first one, side by side with the first car
+pub fn join_players(
+    mut commands: Commands,
+    count: Res<PlayerCount>,
+    map: Option<Res<CityMap>>,
+    player_q: Query<&Player>,
+) {
+    if player_q.iter().count() >= count.0 {
+        return;
+    }
+    let start = map
+        .and_then(|map| map.start)
+        .unwrap_or(Vec2::new(16_355.2, 10_900.));
+    for index in 0..count.0.min(PlayerInput::MAPS.len()) {
+        if player_q.iter().any(|player| player.0 == index) {
+            continue;
+        }
+        let pos = start + Vec2::X * PLAYER_SPACING * index as f32;
+        CarBundle::spawn(&mut commands).with_player(index).at(pos);
+    }
+}
+
 #[derive(Bundle, Default)]
 pub struct TireBundle {
     tire: Tire,
@@ -171,13 +359,32 @@ pub struct TireBundle {
 #[derive(Component, Default)]
 pub struct Tire {
     force: Vec2,
+    /// Sideways speed of the tire on the last physics tick
+    pub slip: f32,
 }
 
 #[derive(Component)]
 pub struct Steering;
 
+/// Blinks while the car is turning towards its side
+#[derive(Component)]
+pub struct TurnSignal {
+    pub left: bool,
+}
+
+/// How much of the sideways slide the tires cancel each tick, 1 on a dry road
+#[derive(Resource)]
+pub struct TireGrip(pub f32);
+
+impl Default for TireGrip {
+    fn default() -> Self {
+        Self(1.)
+    }
+}
+
 pub fn tire_friction(
     time: Res<Time>,
+    grip: Res<TireGrip>,
     mut tires: Query<
         (
             &mut Tire,
@@ -197,20 +404,37 @@ pub fn tire_friction(
                 .inverse()
                 .mul_vec3(Vec3::new(velocity.linvel.x, velocity.linvel.y, 0.))
                 .xy();
+            tire.slip = local_velocity.x;
             // TODO: The constant 0.25 depends on the car having 4 tires. If more are needed, this needs to be changed to actually count the number of tires.
-            tire.force.x +=
-                -1. * local_velocity.x * (0.25 * car_mass.mass + mass.mass) / time.delta_seconds();
+            tire.force.x += -grip.0 * local_velocity.x * (0.25 * car_mass.mass + mass.mass)
+                / time.delta_seconds();
         }
     }
 }
 
+/// Drives the player cars with their keys and other cars with their [`CarControls`]
+#[allow(clippy::type_complexity)]
 pub fn car_control(
     keyboard_input: Res<Input<KeyCode>>,
     constants: Res<Constants>,
-    cars: Query<(&Velocity, &GlobalTransform, &Children), (With<Player>, Without<Tire>)>,
+    cars: Query<
+        (
+            &Velocity,
+            &GlobalTransform,
+            &Children,
+            Option<&CarControls>,
+            Option<&PlayerInput>,
+        ),
+        Without<Tire>,
+    >,
     mut tires: Query<(&mut Tire, &mut ImpulseJoint), With<Steering>>,
 ) {
-    for (velocity, car_transform, car_tires) in cars.iter() {
+    for (velocity, car_transform, car_tires, controls, input) in cars.iter() {
+        let controls = match (input, controls) {
+            (Some(input), _) => input.controls(&keyboard_input),
+            (None, Some(controls)) => *controls,
+            (None, None) => continue,
+        };
         // Compute car velocity
         let velocity = car_transform
             .to_scale_rotation_translation()
@@ -219,13 +443,12 @@ pub fn car_control(
             .mul_vec3(Vec3::new(velocity.linvel.x, velocity.linvel.y, 0.))
             .y;
         let mut acceleration_force = 0.;
-        let mut steering = 0.;
-        if keyboard_input.pressed(KeyCode::Up) {
+        if controls.accelerate {
             if velocity < constants.car.max_speed {
                 acceleration_force += constants.car.acceleration;
             }
         }
-        if keyboard_input.pressed(KeyCode::Down) {
+        if controls.brake {
             if velocity > 0. {
                 // Breaking
                 acceleration_force -= constants.car.breaking_force;
@@ -235,13 +458,7 @@ pub fn car_control(
                 acceleration_force -= constants.car.acceleration;
             }
         }
-        if keyboard_input.pressed(KeyCode::Left) {
-            steering += constants.car.max_steer;
-        }
-        if keyboard_input.pressed(KeyCode::Right) {
-            steering += -constants.car.max_steer;
-        }
-
+        let mut steering = controls.steer.clamp(-1., 1.) * constants.car.max_steer;
         steering *= (-velocity.abs() * 0.001).exp();
 
         for &t in car_tires {
@@ -274,3 +491,24 @@ pub fn update_tire_forces(mut tires: Query<(&mut Tire, &GlobalTransform, &mut Ex
         tire.force = Vec2::new(0., 0.);
     }
 }
+
+pub fn update_turn_signals(
+    keyboard_input: Res<Input<KeyCode>>,
+    input_q: Query<&PlayerInput>,
+    mut signal_q: Query<(&TurnSignal, &Parent, &mut Visibility)>,
+) {
+    for (signal, parent, mut visibility) in signal_q.iter_mut() {
+        let Ok(input) = input_q.get(parent.get()) else {
+            continue;
+        };
+        let key = if signal.left { input.left } else { input.right };
+        let new_visibility = if keyboard_input.pressed(key) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Seeded city generator, an alternative to the hand made `Level_0` map
+//!
+//! Roads form an irregular grid cut by a river with bridges, and the blocks between them are
+//! filled by district: dense buildings downtown, buildings and parks around it and parks and
+//! parking lots on the outskirts.
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    constants::TILE_SIZE,
+    tilemap::{Direction, TileType},
+    utility::Rng,
+};
+
+/// Passengers picked up and dropped off in a generated city
+const TARGET_COUNT: usize = 12;
+/// Tiles between a pickup and its drop-off at least, so every ride goes somewhere
+const MIN_RIDE_TILES: i32 = 8;
+
+#[derive(Debug, Clone)]
+pub struct City {
+    /// Rows of tiles from the bottom up, like the maps loaded from `Map.csv`
+    pub tiles: Vec<Vec<TileType>>,
+    /// Where the player starts, on a road near the center
+    pub start: Vec2,
+    /// Pickup and drop-off points of the missions in turn, on the curb next to a road
+    pub targets: Vec<Vec2>,
+}
+
+impl City {
+    pub fn generate(seed: u64, size: IVec2) -> Self {
+        let mut rng = Rng::new(seed);
+        let size = size.max(IVec2::splat(8));
+        let mut tiles = vec![vec![TileType::Grass; size.x as usize]; size.y as usize];
+
+        // Road lines with blocks of varying size between them, always on the map edges too
+        let lines = |rng: &mut Rng, len: i32| {
+            let mut lines = vec![0];
+            while let Some(&last) = lines.last() {
+                let next = last + rng.range(3, 6);
+                if next >= len - 2 {
+                    break;
+                }
+                lines.push(next);
+            }
+            lines.push(len - 1);
+            lines
+        };
+        let columns = lines(&mut rng, size.x);
+        let rows = lines(&mut rng, size.y);
+        for &x in &columns {
+            for row in tiles.iter_mut() {
+                row[x as usize] = TileType::Road;
+            }
+        }
+        for &y in &rows {
+            tiles[y as usize].fill(TileType::Road);
+        }
+
+        // Drop some road segments between junctions, as long as the roads stay connected
+        for (i, &y) in rows
+            .iter()
+            .enumerate()
+            .skip(1)
+            .take(rows.len().saturating_sub(2))
+        {
+            for pair in columns.windows(2) {
+                if !rng.chance(0.2) {
+                    continue;
+                }
+                let segment: Vec<IVec2> =
+                    (pair[0] + 1..pair[1]).map(|x| IVec2::new(x, y)).collect();
+                set_all(&mut tiles, &segment, TileType::Grass);
+                if !roads_connected(&tiles) {
+                    set_all(&mut tiles, &segment, TileType::Road);
+                }
+            }
+            // Some of the remaining rows are one-way, alternating directions
+            if rng.chance(0.3) {
+                let direction = if i % 2 == 0 {
+                    Direction::East
+                } else {
+                    Direction::West
+                };
+                for x in 0..size.x {
+                    let on_column = columns.contains(&x);
+                    let tile = &mut tiles[y as usize][x as usize];
+                    if *tile == TileType::Road && !on_column {
+                        *tile = TileType::OneWay(direction);
+                    }
+                }
+            }
+        }
+
+        // Blocks filled by district, by distance from the center
+        let center = size.as_vec2() / 2.;
+        let radius = center.length();
+        for rows in rows.windows(2) {
+            for columns in columns.windows(2) {
+                let min = IVec2::new(columns[0] + 1, rows[0] + 1);
+                let max = IVec2::new(columns[1] - 1, rows[1] - 1);
+                if min.x > max.x || min.y > max.y {
+                    continue;
+                }
+                let distance = ((min + max).as_vec2() / 2.).distance(center) / radius;
+                let fill = if distance < 0.3 {
+                    if rng.chance(0.1) {
+                        TileType::Parking
+                    } else {
+                        TileType::Building
+                    }
+                } else if distance < 0.6 {
+                    if rng.chance(0.25) {
+                        TileType::Park
+                    } else {
+                        TileType::Building
+                    }
+                } else {
+                    match rng.range(0, 5) {
+                        0 => TileType::Parking,
+                        1 | 2 => TileType::Park,
+                        _ => TileType::Grass,
+                    }
+                };
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        tiles[y as usize][x as usize] = fill;
+                    }
+                }
+            }
+        }
+
+        // A river through one column of blocks, bridged where roads cross it
+        let river_blocks: Vec<i32> = columns
+            .windows(2)
+            .filter(|pair| pair[1] - pair[0] >= 4)
+            .map(|pair| pair[0] + 2)
+            .collect();
+        if !river_blocks.is_empty() {
+            let x = river_blocks[rng.range(0, river_blocks.len() as i32 - 1) as usize];
+            for row in tiles.iter_mut() {
+                for tile in &mut row[x as usize..=x as usize + 1] {
+                    *tile = if tile.is_road() {
+                        TileType::Bridge
+                    } else {
+                        TileType::Water
+                    };
+                }
+            }
+        }
+
+        let start_tile = closest_road(&tiles, center.as_ivec2()).unwrap_or(IVec2::ZERO);
+        let targets = place_targets(&tiles, start_tile, &mut rng);
+        Self {
+            tiles,
+            start: start_tile.as_vec2() * TILE_SIZE,
+            targets,
+        }
+    }
+}
+
+fn get(tiles: &[Vec<TileType>], pos: IVec2) -> Option<TileType> {
+    let row = tiles.get(usize::try_from(pos.y).ok()?)?;
+    row.get(usize::try_from(pos.x).ok()?).copied()
+}
+
+fn set_all(tiles: &mut [Vec<TileType>], positions: &[IVec2], tp: TileType) {
+    for pos in positions {
+        tiles[pos.y as usize][pos.x as usize] = tp;
+    }
+}
+
+const SIDES: [IVec2; 4] = [IVec2::Y, IVec2::X, IVec2::NEG_Y, IVec2::NEG_X];
+
+/// Road tiles reachable from `start` along roads
+fn reachable_roads(tiles: &[Vec<TileType>], start: IVec2) -> HashSet<IVec2> {
+    let mut reached = HashSet::new();
+    if !get(tiles, start).is_some_and(|tp| tp.is_road()) {
+        return reached;
+    }
+    let mut open = vec![start];
+    reached.insert(start);
+    while let Some(pos) = open.pop() {
+        for side in SIDES {
+            let next = pos + side;
+            if get(tiles, next).is_some_and(|tp| tp.is_road()) && reached.insert(next) {
+                open.push(next);
+            }
+        }
+    }
+    reached
+}
+
+fn road_tiles(tiles: &[Vec<TileType>]) -> Vec<IVec2> {
+    let mut roads = Vec::new();
+    for (y, row) in tiles.iter().enumerate() {
+        for (x, tp) in row.iter().enumerate() {
+            if tp.is_road() {
+                roads.push(IVec2::new(x as i32, y as i32));
+            }
+        }
+    }
+    roads
+}
+
+fn roads_connected(tiles: &[Vec<TileType>]) -> bool {
+    let roads = road_tiles(tiles);
+    roads.first().map_or(true, |&first| {
+        reachable_roads(tiles, first).len() == roads.len()
+    })
+}
+
+fn closest_road(tiles: &[Vec<TileType>], pos: IVec2) -> Option<IVec2> {
+    road_tiles(tiles)
+        .into_iter()
+        .filter(|&road| get(tiles, road) == Some(TileType::Road))
+        .min_by_key(|road| (*road - pos).length_squared())
+}
+
+/// Alternating pickups and drop-offs on reachable roads next to buildings or parks, moved to the
+/// curb on that side
+fn place_targets(tiles: &[Vec<TileType>], start: IVec2, rng: &mut Rng) -> Vec<Vec2> {
+    let mut curbs: Vec<(IVec2, IVec2)> = reachable_roads(tiles, start)
+        .into_iter()
+        .filter(|&pos| get(tiles, pos) == Some(TileType::Road))
+        .filter_map(|pos| {
+            let side = SIDES.into_iter().find(|&side| {
+                matches!(
+                    get(tiles, pos + side),
+                    Some(TileType::Building | TileType::Park | TileType::Parking)
+                )
+            })?;
+            Some((pos, side))
+        })
+        .collect();
+    curbs.sort_by_key(|(pos, _)| (pos.y, pos.x));
+    if curbs.is_empty() {
+        return Vec::new();
+    }
+
+    let mut targets: Vec<(IVec2, IVec2)> = Vec::new();
+    while targets.len() < TARGET_COUNT {
+        let (pos, side) = curbs[rng.below(curbs.len())];
+        // Drop-offs far from their pickup, as long as there are such curbs at all
+        let far_enough = match targets.last() {
+            Some((last, _)) if targets.len() % 2 == 1 => {
+                (pos - *last).abs().max_element() >= MIN_RIDE_TILES
+                    || curbs
+                        .iter()
+                        .all(|(other, _)| (*other - *last).abs().max_element() < MIN_RIDE_TILES)
+            }
+            _ => true,
+        };
+        if far_enough {
+            targets.push((pos, side));
+        }
+    }
+    targets
+        .into_iter()
+        .map(|(pos, side)| (pos.as_vec2() + side.as_vec2() / 4.) * TILE_SIZE)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cities_are_seeded() {
+        let a = City::generate(7, IVec2::new(30, 24));
+        let b = City::generate(7, IVec2::new(30, 24));
+        let c = City::generate(8, IVec2::new(30, 24));
+        assert_eq!(a.tiles, b.tiles);
+        assert_eq!(a.targets, b.targets);
+        assert_ne!(a.tiles, c.tiles);
+    }
+
+    #[test]
+    fn generated_cities_are_drivable() {
+        for seed in 0..20 {
+            let city = City::generate(seed, IVec2::new(40, 40));
+            assert!(roads_connected(&city.tiles), "seed {seed}");
+            assert!(city
+                .tiles
+                .iter()
+                .flatten()
+                .any(|tp| *tp == TileType::Building));
+            assert_eq!(city.targets.len(), TARGET_COUNT, "seed {seed}");
+
+            // Every target is beside a road the start is connected to
+            let start = (city.start / TILE_SIZE).round().as_ivec2();
+            let roads = reachable_roads(&city.tiles, start);
+            for target in &city.targets {
+                let tile = (*target / TILE_SIZE).round().as_ivec2();
+                assert!(roads.contains(&tile), "seed {seed}: {target}");
+            }
+        }
+    }
+}
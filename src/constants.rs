@@ -15,6 +15,11 @@ pub struct Constants {
     pub car: CarConstants,
     pub camera: CameraConstants,
     pub trigger: TriggerConstants,
+    pub audio: AudioConstants,
+    pub day_night: DayNightConstants,
+    pub weather: WeatherConstants,
+    pub map: MapConstants,
+    pub traffic: TrafficConstants,
 }
 
 #[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
@@ -103,3 +108,142 @@ impl Default for UiConstants {
         }
     }
 }
+
+#[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
+#[reflect(InspectorOptions)]
+pub struct AudioConstants {
+    #[inspector(min = 0.0, max = 1.0)]
+    pub master_volume: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub music_volume: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub engine_volume: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub sfx_volume: f32,
+    /// Seconds it takes for one music track to fade into the next
+    #[inspector(min = 0.0, max = 10.0)]
+    pub music_crossfade: f32,
+    /// Music volume multiplier while someone is talking
+    #[inspector(min = 0.0, max = 1.0)]
+    pub radio_duck: f32,
+    /// Lateral tire speed at which tires start screeching
+    pub screech_slip: f32,
+}
+
+impl Default for AudioConstants {
+    fn default() -> Self {
+        Self {
+            master_volume: 0.8,
+            music_volume: 0.5,
+            engine_volume: 0.6,
+            sfx_volume: 0.8,
+            music_crossfade: 3.,
+            radio_duck: 0.3,
+            screech_slip: 150.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
+#[reflect(InspectorOptions)]
+pub struct DayNightConstants {
+    /// Real seconds in one in-game day
+    #[inspector(min = 10.0, max = 3600.0)]
+    pub day_length: f32,
+    /// Opacity of the night tint at midnight
+    #[inspector(min = 0.0, max = 1.0)]
+    pub night_darkness: f32,
+    pub night_color: Color,
+    pub light_color: Color,
+    pub street_light_radius: f32,
+}
+
+impl Default for DayNightConstants {
+    fn default() -> Self {
+        Self {
+            day_length: 600.,
+            night_darkness: 0.75,
+            night_color: Color::rgb(0.02, 0.03, 0.15),
+            light_color: Color::rgb(1., 0.9, 0.6),
+            street_light_radius: 700.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
+#[reflect(InspectorOptions)]
+pub struct WeatherConstants {
+    /// Seconds for one weather to fade out or in
+    #[inspector(min = 0.0, max = 60.0)]
+    pub transition_time: f32,
+    /// Seconds between random weather changes outside missions
+    #[inspector(min = 1.0, max = 1200.0)]
+    pub change_interval: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub rain_grip: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub snow_grip: f32,
+    pub rain_visibility: f32,
+    pub snow_visibility: f32,
+    pub fog_visibility: f32,
+    pub fog_color: Color,
+}
+
+impl Default for WeatherConstants {
+    fn default() -> Self {
+        Self {
+            transition_time: 8.,
+            change_interval: 120.,
+            rain_grip: 0.6,
+            snow_grip: 0.35,
+            rain_visibility: 3000.,
+            snow_visibility: 2000.,
+            fog_visibility: 900.,
+            fog_color: Color::rgb(0.75, 0.78, 0.8),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
+#[reflect(InspectorOptions)]
+pub struct MapConstants {
+    /// Width and height of the map chunks in tiles
+    #[inspector(min = 1, max = 64)]
+    pub chunk_size: i32,
+    /// Chunks around the camera's chunk that are kept spawned, in each direction
+    #[inspector(min = 0, max = 16)]
+    pub stream_radius: i32,
+}
+
+impl Default for MapConstants {
+    fn default() -> Self {
+        Self {
+            chunk_size: 4,
+            stream_radius: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Resource, Reflect, InspectorOptions)]
+#[reflect(InspectorOptions)]
+pub struct TrafficConstants {
+    /// Speed below which the car isn't considered to be driving in any direction
+    #[inspector(min = 0.0, max = 1000.0)]
+    pub min_speed: f32,
+    /// Speed above which entering a junction counts as running through it
+    #[inspector(min = 0.0, max = 2000.0)]
+    pub crossing_speed: f32,
+    /// How far over the center line the car can be before it's on the wrong side, in tiles
+    #[inspector(min = 0.0, max = 0.5)]
+    pub lane_margin: f32,
+}
+
+impl Default for TrafficConstants {
+    fn default() -> Self {
+        Self {
+            min_speed: 100.,
+            crossing_speed: 500.,
+            lane_margin: 0.04,
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! World clock darkening the scene at night and switching on headlights and street lights
+
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{
+    constants::{Constants, CAR_SPRITE_SCALE},
+    missions::MissionState,
+    parallax::LightDirection,
+    plugins::GameSet,
+    utility::alpha_image,
+};
+
+/// Height of the darkening overlay, above every parallax layer
+pub const NIGHT_Z: f32 = 55.;
+/// Height of light cones, drawn on top of the overlay so they stay bright
+pub const LIGHT_Z: f32 = 56.;
+const LIGHT_TEXTURE_SIZE: u32 = 64;
+
+pub struct DayNightPlugin;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldClock>()
+            .add_systems(Startup, (setup_day_night, setup_clock_ui))
+            .add_systems(Update, advance_clock.in_set(GameSet::Gameplay))
+            .add_systems(
+                Update,
+                (add_light_cones, update_lighting, update_sun)
+                    .chain()
+                    .after(advance_clock)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(Update, update_clock_ui.in_set(GameSet::Ui));
+    }
+}
+
+/// Time of day in the game world
+#[derive(Resource, Debug)]
+pub struct WorldClock {
+    /// Hours since midnight, between 0 and 24
+    pub hour: f32,
+    pub day: u32,
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self { hour: 17., day: 0 }
+    }
+}
+
+impl WorldClock {
+    /// Whether the clock is between `start` and `end`, wrapping over midnight if `end < start`
+    pub fn is_between(&self, (start, end): (f32, f32)) -> bool {
+        if start <= end {
+            (start..end).contains(&self.hour)
+        } else {
+            self.hour >= start || self.hour < end
+        }
+    }
+
+    /// Shadow offset per unit of height, from the sun rising in the east and setting in the west
+    pub fn sun_offset(&self) -> Vec2 {
+        // 0 at sunrise, PI/2 at noon and PI at sunset
+        let angle = std::f32::consts::PI * (self.hour - 6.) / 12.;
+        // Slightly from the south, and never so low that shadows grow endlessly long
+        let towards_sun = Vec2::new(angle.cos(), -0.3).normalize();
+        let length = angle.cos().abs() / angle.sin().max(0.2);
+        -towards_sun * length
+    }
+
+    /// 0 during the day and 1 at night, blending over dusk and dawn
+    pub fn darkness(&self) -> f32 {
+        // Height of the sun, 1 at noon and -1 at midnight
+        let sun = (std::f32::consts::TAU * (self.hour - 12.) / 24.).cos();
+        let t = ((0.25 - sun) / 0.5).clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
+}
+
+/// Marks a car's headlights, which are only on at night
+#[derive(Component)]
+pub struct Headlights;
+
+/// Marks a lamppost that lights up at night
+#[derive(Component)]
+pub struct StreetLight;
+
+/// Light sprite faded in with the darkness
+#[derive(Component)]
+pub struct LightCone {
+    /// Sprite alpha at full night
+    intensity: f32,
+}
+
+/// Dark tint covering the whole scene
+#[derive(Component)]
+pub struct NightOverlay;
+
+#[derive(Resource)]
+pub struct LightTextures {
+    glow: Handle<Image>,
+    cone: Handle<Image>,
+}
+
+pub fn setup_day_night(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    constants: Res<Constants>,
+) {
+    let glow = alpha_image(LIGHT_TEXTURE_SIZE, |p| (1. - p.length()).powi(2));
+    // Apex at the bottom center, widening and fading towards the top
+    let cone = alpha_image(LIGHT_TEXTURE_SIZE, |p| {
+        let along = (p.y + 1.) / 2.;
+        let across = p.x.abs() / along.max(0.01);
+        (1. - along) * (1. - across)
+    });
+    commands.insert_resource(LightTextures {
+        glow: images.add(glow),
+        cone: images.add(cone),
+    });
+
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: constants.day_night.night_color.with_a(0.),
+                custom_size: Some(Vec2::splat(1_000_000.)),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(0., 0., NIGHT_Z),
+            ..Default::default()
+        },
+        NightOverlay,
+    ));
+}
+
+pub fn advance_clock(time: Res<Time>, constants: Res<Constants>, mut clock: ResMut<WorldClock>) {
+    clock.hour += 24. * time.delta_seconds() / constants.day_night.day_length.max(1.);
+    if clock.hour >= 24. {
+        clock.hour -= 24.;
+        clock.day += 1;
+    }
+}
+
+pub fn add_light_cones(
+    mut commands: Commands,
+    textures: Res<LightTextures>,
+    constants: Res<Constants>,
+    headlights_q: Query<Entity, Added<Headlights>>,
+    street_light_q: Query<Entity, Added<StreetLight>>,
+) {
+    let color = constants.day_night.light_color.with_a(0.);
+    for entity in headlights_q.iter() {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                SpriteBundle {
+                    texture: textures.cone.clone(),
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(CAR_SPRITE_SCALE * Vec2::new(80., 140.)),
+                        anchor: Anchor::BottomCenter,
+                        ..Default::default()
+                    },
+                    transform: Transform::from_xyz(0., 0., LIGHT_Z),
+                    ..Default::default()
+                },
+                LightCone { intensity: 0.6 },
+            ));
+        });
+    }
+    for entity in street_light_q.iter() {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                SpriteBundle {
+                    texture: textures.glow.clone(),
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(Vec2::splat(constants.day_night.street_light_radius)),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_xyz(0., 0., LIGHT_Z),
+                    ..Default::default()
+                },
+                LightCone { intensity: 0.4 },
+            ));
+        });
+    }
+}
+
+pub fn update_lighting(
+    clock: Res<WorldClock>,
+    constants: Res<Constants>,
+    mut overlay_q: Query<&mut Sprite, (With<NightOverlay>, Without<LightCone>)>,
+    mut light_q: Query<(&LightCone, &mut Sprite, &mut Visibility)>,
+    mut headlights_q: Query<&mut Visibility, (With<Headlights>, Without<LightCone>)>,
+) {
+    let darkness = clock.darkness();
+    let lights_on = darkness > 0.;
+
+    for mut sprite in overlay_q.iter_mut() {
+        sprite.color = constants
+            .day_night
+            .night_color
+            .with_a(constants.day_night.night_darkness * darkness);
+    }
+    for (light, mut sprite, mut visibility) in light_q.iter_mut() {
+        sprite.color = constants
+            .day_night
+            .light_color
+            .with_a(light.intensity * darkness);
+        *visibility = if lights_on {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+    for mut visibility in headlights_q.iter_mut() {
+        *visibility = if lights_on {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+/// Turns shadows with the sun and fades them out for the night
+pub fn update_sun(clock: Res<WorldClock>, mut light: ResMut<LightDirection>) {
+    light.offset = clock.sun_offset();
+    light.strength = 1. - clock.darkness();
+}
+
+#[derive(Component)]
+pub struct ClockText {}
+
+pub fn setup_clock_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font,
+                font_size: constants.ui.font_size * 0.5,
+                color: constants.ui.font_color,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(constants.ui.fps_text_padding),
+            top: Val::Px(constants.ui.fps_text_padding + constants.ui.font_size * 0.5),
+            ..default()
+        }),
+        ClockText {},
+    ));
+}
+
+fn format_hour(hour: f32) -> String {
+    let minutes = (hour * 60.) as u32;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+pub fn update_clock_ui(
+    clock: Res<WorldClock>,
+    mission_state: Res<MissionState>,
+    mut ui_q: Query<&mut Text, With<ClockText>>,
+) {
+    let mut label = format!("Day {} {}", clock.day + 1, format_hour(clock.hour));
+    if let Some(hours) = mission_state.pickup_hours() {
+        if !clock.is_between(hours) {
+            label += &format!("\nNext fare at {}", format_hour(hours.0));
+        }
+    }
+    for mut text in &mut ui_q {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn darkness_follows_the_sun() {
+        let at = |hour| WorldClock { hour, day: 0 }.darkness();
+        assert_eq!(at(12.), 0.);
+        assert_eq!(at(0.), 1.);
+        assert!(at(18.) > 0. && at(18.) < 1.);
+    }
+
+    #[test]
+    fn shadows_point_away_from_the_sun() {
+        let at = |hour| WorldClock { hour, day: 0 }.sun_offset();
+        assert!(at(8.).x < 0.);
+        assert!(at(16.).x > 0.);
+        assert!(at(12.).length() < at(8.).length());
+    }
+
+    #[test]
+    fn schedule_wraps_over_midnight() {
+        let clock = WorldClock { hour: 2., day: 0 };
+        assert!(clock.is_between((22., 4.)));
+        assert!(!clock.is_between((8., 20.)));
+    }
+}
@@ -1,8 +1,44 @@
 use bevy::prelude::*;
+use bevy_common_assets::json::JsonAssetPlugin;
 
 use std::{collections::VecDeque, time::Duration};
 
-use crate::constants::Constants;
+use crate::{
+    addresses::AddressBook,
+    constants::Constants,
+    missions::{handle_mission_triggers, MissionEvent, Ride},
+    plugins::GameSet,
+    trigger::{route_trigger_collisions, TriggerEntered, TriggerType},
+    weather::{choose_weather, WeatherState},
+};
+
+pub struct DialoguePlugin;
+
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(JsonAssetPlugin::<DialogueList>::new(&["dialogues.json"]))
+            .insert_resource(DialogueState::default())
+            .add_event::<DialogueEffect>()
+            .add_systems(
+                Startup,
+                (setup_dialogues, setup_dialogue_ui.after(setup_dialogues)),
+            )
+            .add_systems(
+                Update,
+                handle_mission_dialogue
+                    .after(handle_mission_triggers)
+                    .after(choose_weather)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(
+                Update,
+                handle_dialogue_zones
+                    .after(route_trigger_collisions)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(Update, handle_dialogue_ui.in_set(GameSet::Ui));
+    }
+}
 
 #[derive(Debug, serde::Deserialize, bevy::asset::Asset, bevy::reflect::TypePath)]
 #[serde(transparent)]
@@ -14,6 +50,10 @@ impl DialogueList {
     pub fn get(&self, name: &str) -> Option<Dialogue> {
         self.list.iter().find(|d| d.name == name).cloned()
     }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.list.iter().any(|d| d.name == name)
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, bevy::asset::Asset, bevy::reflect::TypePath)]
@@ -24,6 +64,16 @@ pub struct Dialogue {
 }
 
 impl Dialogue {
+    /// Replaces `{placeholder}` in every line still to be said with `value`
+    pub fn fill(&mut self, placeholder: &str, value: &str) {
+        let pattern = format!("{{{placeholder}}}");
+        for section in self.current.iter_mut().chain(self.list.iter_mut()) {
+            for line in section.list.iter_mut() {
+                *line = line.replace(&pattern, value);
+            }
+        }
+    }
+
     pub fn next_dialogue(&mut self) -> Option<DialogueContent> {
         match &mut self.current {
             Some(section) => {
@@ -33,7 +83,13 @@ impl Dialogue {
                     return self.next_dialogue();
                 }
 
-                return Some(DialogueContent::new(section.character, text.unwrap()));
+                // Effects of a section happen together with its first line
+                let effects = std::mem::take(&mut section.effects);
+                return Some(DialogueContent::new(
+                    section.character,
+                    text.unwrap(),
+                    effects,
+                ));
             }
             None => {
                 self.current = self.list.pop_front();
@@ -52,21 +108,34 @@ impl Dialogue {
 pub struct DialogueContent {
     character: DialogueCharacter,
     text: String,
+    effects: Vec<DialogueEffect>,
 }
 
 impl DialogueContent {
-    fn new(character: DialogueCharacter, text: String) -> Self {
-        Self { text, character }
+    fn new(character: DialogueCharacter, text: String, effects: Vec<DialogueEffect>) -> Self {
+        Self {
+            text,
+            character,
+            effects,
+        }
     }
 }
 
-#[derive(Resource)]
+/// Something a line of dialogue does to the game, sent as an event when the line is shown
+#[derive(Debug, Clone, Event, serde::Deserialize)]
+pub enum DialogueEffect {
+    RequestStation(String),
+}
+
+#[derive(Resource, Default)]
 pub struct DialogueHandle(Handle<DialogueList>);
 
 #[derive(Debug, Clone, serde::Deserialize, bevy::asset::Asset, bevy::reflect::TypePath)]
 pub struct DialogueSection {
     pub character: DialogueCharacter,
     pub list: VecDeque<String>,
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
 }
 
 impl DialogueSection {
@@ -146,9 +215,85 @@ pub fn setup_dialogues(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(p1);
 }
 
+pub fn setup_dialogue_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn(DialogueBundle::new(constants, font));
+}
+
+/// Starts the passenger's dialogue on pickup and the goodbye on drop-off
+///
+/// Uses the `-rain`, `-snow` or `-fog` variant of the dialogue if there is one for the weather.
+/// `{destination}` in the lines is the address of the drop-off.
+pub fn handle_mission_dialogue(
+    mut mission_events: EventReader<MissionEvent>,
+    mut state: ResMut<DialogueState>,
+    mut dialogues: ResMut<Assets<DialogueList>>,
+    dialogue: Res<DialogueHandle>,
+    weather: Option<Res<WeatherState>>,
+    ride_q: Query<&Ride>,
+    addresses: Option<Res<AddressBook>>,
+) {
+    for event in mission_events.read() {
+        let (name, car) = match *event {
+            MissionEvent::Started(mission, car) => (format!("p{mission}"), car),
+            MissionEvent::Finished(_, car) => (String::from("p-end"), car),
+        };
+        let weather_name = weather
+            .as_ref()
+            .map(|weather| format!("{name}-{}", weather.target.key()));
+        let name = match weather_name {
+            Some(weather_name)
+                if dialogues
+                    .get(dialogue.0.id())
+                    .is_some_and(|list| list.contains(&weather_name)) =>
+            {
+                weather_name
+            }
+            _ => name,
+        };
+        state.load_dialogue(&name, &mut dialogues, &dialogue);
+
+        let destination = ride_q
+            .get(car)
+            .ok()
+            .and_then(|ride| ride.dropoff)
+            .zip(addresses.as_ref())
+            .and_then(|(target, addresses)| addresses.address(target))
+            .map_or_else(
+                || String::from("the usual place"),
+                |address| address.to_string(),
+            );
+        if let Some(current) = &mut state.current {
+            current.fill("destination", &destination);
+        }
+    }
+}
+
+/// Plays ambient dialogue from dialogue zones, unless someone is already talking
+pub fn handle_dialogue_zones(
+    mut entered: EventReader<TriggerEntered>,
+    trigger_q: Query<&TriggerType>,
+    mut state: ResMut<DialogueState>,
+    mut dialogues: ResMut<Assets<DialogueList>>,
+    dialogue: Res<DialogueHandle>,
+) {
+    for TriggerEntered { trigger, .. } in entered.read() {
+        if let Ok(TriggerType::Dialogue { name }) = trigger_q.get(*trigger) {
+            if !state.active {
+                state.load_dialogue(name, &mut dialogues, &dialogue);
+            }
+        }
+    }
+}
+
 pub fn handle_dialogue_ui(
     mut ui_q: Query<&mut Text, With<DialogueText>>,
     mut state: ResMut<DialogueState>,
+    mut effects: EventWriter<DialogueEffect>,
     time: Res<Time>,
     _dialogues: ResMut<Assets<DialogueList>>,
     _dialogue: Res<DialogueHandle>,
@@ -160,6 +305,7 @@ pub fn handle_dialogue_ui(
         if state.timer.finished() {
             if let Some(current) = &mut state.current {
                 if let Some(content) = current.next_dialogue() {
+                    effects.send_batch(content.effects);
                     for mut text in &mut ui_q {
                         text.sections[0].value = format!("{:?}: ", content.character);
                         text.sections[1].value = content.text.clone();
@@ -175,3 +321,30 @@ pub fn handle_dialogue_ui(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_filled() {
+        let mut dialogue: Dialogue = serde_json::from_str(
+            r#"{
+                "name": "p1",
+                "list": [
+                    { "character": "Passenger", "list": ["Hey", "Take me to {destination}"] },
+                    { "character": "Driver", "list": ["{destination}? Sure"] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        dialogue.fill("destination", "3 Tango Street");
+        let lines: Vec<String> = std::iter::from_fn(|| dialogue.next_dialogue())
+            .map(|content| content.text)
+            .collect();
+        assert_eq!(
+            lines,
+            ["Hey", "Take me to 3 Tango Street", "3 Tango Street? Sure"]
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Level editor toggled with F3. Paints tiles with the mouse, places pieces, mission points and
+//! trigger zones, and exports the level back to the files it is loaded from
+
+use std::{error::Error, f32::consts::PI, fs, path::Path};
+
+use bevy::{input::common_conditions::input_toggle_active, prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContext};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    camera::PlayerCamera,
+    constants::TILE_SIZE,
+    missions::MissionState,
+    plugins::GameSet,
+    tilemap::{
+        to_csv, ChunkedMap, Direction, MapSource, PiecePlacement, Placements, TileType,
+        MAP_CSV_PATH, PLACEMENTS_PATH,
+    },
+    trigger::{TriggerShape, TriggerType},
+    zones::{level_zones, ZoneDesc, LEVEL_ZONES_PATH},
+};
+
+/// Path of the LDtk project relative to the crate
+const LDTK_PATH: &str = "assets/tilemap.ldtk";
+
+/// Pieces that can be placed anywhere on the map
+const PIECES: [&str; 4] = ["tree", "pole", "parking", "oneway_arrow"];
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Editor>().add_systems(
+            Update,
+            (editor_window, paint, draw_editor)
+                .chain()
+                .in_set(GameSet::Ui)
+                .run_if(input_toggle_active(false, KeyCode::F3)),
+        );
+    }
+}
+
+/// What a left click places
+#[derive(Debug, Clone, PartialEq)]
+pub enum Brush {
+    Tile(TileType),
+    Piece(&'static str),
+    MissionTarget,
+    Zone(TriggerType),
+}
+
+impl Brush {
+    fn label(&self) -> String {
+        match self {
+            Brush::Tile(tp) => format!("{tp:?}"),
+            Brush::Piece(atlas) => atlas.to_string(),
+            Brush::MissionTarget => "Mission point".into(),
+            Brush::Zone(trigger) => format!("{trigger:?}"),
+        }
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct Editor {
+    pub brush: Brush,
+    /// Rotation of placed pieces and zones, turned with Q
+    pub rotation: f32,
+    pub zones: Vec<ZoneDesc>,
+    pub placements: Placements,
+    /// Result of the last export
+    status: String,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            brush: Brush::Tile(TileType::Road),
+            rotation: 0.,
+            zones: level_zones(),
+            placements: Placements::load(),
+            status: String::new(),
+        }
+    }
+}
+
+fn brushes(zones: &[ZoneDesc]) -> Vec<Brush> {
+    use TileType::*;
+    let tiles = [
+        Empty,
+        Grass,
+        Road,
+        Building,
+        Water,
+        Parking,
+        Park,
+        Bridge,
+        OneWay(Direction::North),
+        OneWay(Direction::East),
+        OneWay(Direction::South),
+        OneWay(Direction::West),
+    ];
+    let checkpoints = zones
+        .iter()
+        .filter(|zone| matches!(zone.trigger, TriggerType::Checkpoint { .. }))
+        .count();
+    let zones = [
+        TriggerType::SpeedTrap {
+            limit: 600.,
+            fine: 50,
+        },
+        TriggerType::Restricted { fine: 100 },
+        TriggerType::Checkpoint { index: checkpoints },
+        TriggerType::Garage,
+    ];
+    tiles
+        .into_iter()
+        .map(Brush::Tile)
+        .chain(PIECES.into_iter().map(Brush::Piece))
+        .chain([Brush::MissionTarget])
+        .chain(zones.into_iter().map(Brush::Zone))
+        .collect()
+}
+
+pub fn editor_window(
+    mut editor: ResMut<Editor>,
+    mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
+    map: Option<Res<ChunkedMap>>,
+    source: Option<Res<MapSource>>,
+    mut mission_state: Option<ResMut<MissionState>>,
+) {
+    let Ok(mut egui_context) = egui_context.get_single_mut() else {
+        return;
+    };
+    // Exporting writes over the files of `Level_0`, so a generated city would replace it
+    let exportable = !matches!(source.as_deref(), Some(MapSource::Generated { .. }));
+    let mut export = false;
+    let mut clear_targets = false;
+    egui::Window::new("Editor").show(egui_context.get_mut(), |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for brush in brushes(&editor.zones) {
+                let selected = editor.brush == brush;
+                if ui.selectable_label(selected, brush.label()).clicked() {
+                    editor.brush = brush;
+                }
+            }
+        });
+        ui.label(format!(
+            "Rotation (Q): {:.0}°",
+            editor.rotation.to_degrees()
+        ));
+        ui.horizontal(|ui| {
+            clear_targets = ui.button("Clear mission points").clicked();
+            export = ui
+                .add_enabled(exportable, egui::Button::new("Export"))
+                .on_disabled_hover_text("Generated cities aren't exported over Level_0")
+                .clicked();
+        });
+        ui.label(&editor.status);
+    });
+
+    if clear_targets {
+        if let Some(mission_state) = mission_state.as_mut() {
+            **mission_state = MissionState::with_targets(Vec::new());
+        }
+    }
+    if export && exportable {
+        let Some(map) = map else {
+            return;
+        };
+        editor.placements.targets = mission_state
+            .map(|state| state.targets.clone())
+            .unwrap_or_default();
+        editor.status = match export_level(&map, &editor) {
+            Ok(()) => "Exported".into(),
+            Err(e) => {
+                error!("failed exporting the level: {e}");
+                format!("Export failed: {e}")
+            }
+        };
+    }
+}
+
+/// World position under the mouse, if it isn't over an egui window
+fn cursor_world_pos(
+    window_q: &Query<&Window, With<PrimaryWindow>>,
+    camera_q: &Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+    egui_context: &mut Query<&mut EguiContext, With<PrimaryWindow>>,
+) -> Option<Vec2> {
+    if egui_context
+        .get_single_mut()
+        .is_ok_and(|mut ctx| ctx.get_mut().wants_pointer_input())
+    {
+        return None;
+    }
+    let cursor = window_q.get_single().ok()?.cursor_position()?;
+    // The view of the player the cursor is over, on a split screen
+    camera_q
+        .iter()
+        .filter(|(camera, _)| {
+            camera
+                .logical_viewport_rect()
+                .is_some_and(|view| view.contains(cursor))
+        })
+        .find_map(|(camera, transform)| camera.viewport_to_world_2d(transform, cursor))
+}
+
+fn tile_at(pos: Vec2) -> IVec2 {
+    (pos / TILE_SIZE).round().as_ivec2()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn paint(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    map: Option<ResMut<ChunkedMap>>,
+    mission_state: Option<ResMut<MissionState>>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+    mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
+) {
+    if keys.just_pressed(KeyCode::Q) {
+        editor.rotation = (editor.rotation + PI / 2.) % (2. * PI);
+    }
+    let Some(pos) = cursor_world_pos(&window_q, &camera_q, &mut egui_context) else {
+        return;
+    };
+    let rotation = editor.rotation;
+
+    match editor.brush.clone() {
+        // Tiles are painted while the button is held
+        Brush::Tile(tp) => {
+            if let Some(mut map) = map.filter(|_| mouse.pressed(MouseButton::Left)) {
+                map.set_tile(&mut commands, tile_at(pos), tp);
+            }
+        }
+        _ if !mouse.just_pressed(MouseButton::Left) => {}
+        Brush::Piece(atlas) => {
+            let piece = PiecePlacement {
+                atlas: atlas.into(),
+                position: pos,
+                rotation,
+            };
+            piece.spawn(&mut commands);
+            editor.placements.pieces.push(piece);
+        }
+        Brush::MissionTarget => {
+            if let Some(mut mission_state) = mission_state {
+                mission_state.targets.push(pos);
+            }
+        }
+        Brush::Zone(trigger) => {
+            let zone = ZoneDesc {
+                trigger,
+                shape: TriggerShape::Rectangle {
+                    half_size: Vec2::splat(TILE_SIZE / 2.),
+                },
+                position: pos,
+                rotation,
+            };
+            zone.spawn(&mut commands);
+            editor.zones.push(zone);
+        }
+    }
+}
+
+/// Outlines the tile under the mouse and the mission points in order
+pub fn draw_editor(
+    mut gizmos: Gizmos,
+    editor: Res<Editor>,
+    mission_state: Option<Res<MissionState>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+    mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
+) {
+    if let Some(pos) = cursor_world_pos(&window_q, &camera_q, &mut egui_context) {
+        match editor.brush {
+            Brush::Tile(_) => {
+                let center = tile_at(pos).as_vec2() * TILE_SIZE;
+                gizmos.rect_2d(center, 0., Vec2::splat(TILE_SIZE), Color::YELLOW);
+            }
+            _ => gizmos.circle_2d(pos, TILE_SIZE / 8., Color::YELLOW),
+        }
+    }
+    if let Some(mission_state) = mission_state {
+        gizmos.linestrip_2d(mission_state.targets.iter().copied(), Color::GOLD);
+        for target in &mission_state.targets {
+            gizmos.circle_2d(*target, TILE_SIZE / 6., Color::GOLD);
+        }
+    }
+}
+
+/// Writes the map, zones and placements over the files of the hand made level
+fn export_level(map: &ChunkedMap, editor: &Editor) -> Result<(), Box<dyn Error>> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    fs::write(root.join(MAP_CSV_PATH), to_csv(map.raw_map()))?;
+
+    let ldtk = fs::read_to_string(root.join(LDTK_PATH))?;
+    fs::write(root.join(LDTK_PATH), update_ldtk(&ldtk, map.raw_map())?)?;
+
+    fs::write(
+        root.join(LEVEL_ZONES_PATH),
+        serde_json::to_string_pretty(&editor.zones)?,
+    )?;
+    fs::write(
+        root.join(PLACEMENTS_PATH),
+        serde_json::to_string_pretty(&editor.placements)?,
+    )?;
+    info!("exported the level to {}", root.display());
+    Ok(())
+}
+
+/// Replaces the `Map` layer of `Level_0` in the LDtk project with `raw_map`, keeping the tab
+/// indentation LDtk uses
+fn update_ldtk(ldtk: &str, raw_map: &[Vec<TileType>]) -> serde_json::Result<Vec<u8>> {
+    let mut project: Value = serde_json::from_str(ldtk)?;
+    let width = raw_map.first().map_or(0, Vec::len);
+    let height = raw_map.len();
+    // LDtk rows go from the top down
+    let csv: Vec<i32> = raw_map
+        .iter()
+        .rev()
+        .flatten()
+        .map(|tp| i32::from(*tp))
+        .collect();
+
+    let levels = project["levels"].as_array_mut().into_iter().flatten();
+    for level in levels.filter(|level| level["identifier"] == "Level_0") {
+        let mut grid_size = None;
+        let layers = level["layerInstances"].as_array_mut().into_iter().flatten();
+        for layer in layers.filter(|layer| layer["__identifier"] == "Map") {
+            layer["intGridCsv"] = json!(csv);
+            layer["__cWid"] = json!(width);
+            layer["__cHei"] = json!(height);
+            grid_size = layer["__gridSize"].as_u64();
+        }
+        if let Some(grid_size) = grid_size {
+            level["pxWid"] = json!(width as u64 * grid_size);
+            level["pxHei"] = json!(height as u64 * grid_size);
+        }
+    }
+
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"\t");
+    project.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut out, formatter,
+    ))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LDTK: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/tilemap.ldtk"));
+
+    #[test]
+    fn exported_ldtk_has_the_painted_map() {
+        let mut raw_map = vec![vec![TileType::Grass; 3]; 2];
+        raw_map[1][0] = TileType::Road;
+        let project: Value = serde_json::from_slice(&update_ldtk(LDTK, &raw_map).unwrap()).unwrap();
+
+        let level = &project["levels"][0];
+        let layer = level["layerInstances"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|layer| layer["__identifier"] == "Map")
+            .unwrap();
+        // The road is on the top row, which comes first
+        assert_eq!(layer["intGridCsv"], json!([2, 1, 1, 1, 1, 1]));
+        assert_eq!(layer["__cWid"], 3);
+        assert_eq!(layer["__cHei"], 2);
+        assert_eq!(level["pxWid"], 3 * 64);
+    }
+}
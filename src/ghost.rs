@@ -0,0 +1,216 @@
+//! Ghost car replaying the best run of each mission
+
+use bevy::{prelude::*, utils::HashMap, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    car::{Car, Player},
+    constants::CAR_SPRITE_SCALE,
+    missions::{handle_mission_triggers, MissionEvent},
+    modes::GameMode,
+    parallax::{ParallaxImages, ParallaxSprite},
+    plugins::GameSet,
+    police::Arrested,
+};
+
+/// How many ghost frames ahead of the previous match are searched for the delta readout
+const GHOST_SEARCH_WINDOW: usize = 256;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BestRuns>()
+            .init_resource::<RunRecorder>()
+            .add_systems(
+                Update,
+                handle_run_boundaries
+                    .after(handle_mission_triggers)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(Update, show_ghost_delta.in_set(GameSet::Ui))
+            .add_systems(FixedUpdate, (record_run, replay_ghost.after(record_run)));
+    }
+}
+
+/// Player car transform sampled once per fixed tick
+pub type Run = Vec<Transform>;
+
+/// Fastest finished run of each mission, keyed by mission number
+#[derive(Resource, Default)]
+pub struct BestRuns {
+    pub by_mission: HashMap<usize, Run>,
+}
+
+#[derive(Resource, Default)]
+pub struct RunRecorder {
+    /// Mission currently being recorded
+    mission: Option<usize>,
+    /// Car of the player whose run is being recorded
+    car: Option<Entity>,
+    frames: Run,
+    /// Index of the ghost frame closest to the player on the previous frame
+    ghost_match: usize,
+}
+
+#[derive(Component)]
+pub struct Ghost {
+    frames: Run,
+    tick: usize,
+}
+
+#[derive(Bundle)]
+pub struct GhostBundle {
+    ghost: Ghost,
+    sprite: ParallaxSprite,
+}
+
+impl GhostBundle {
+    pub fn new(frames: Run) -> Self {
+        let sprite = Sprite {
+            color: Color::rgba(1., 1., 1., 0.4),
+            custom_size: Some(CAR_SPRITE_SCALE * Vec2::new(64., 64.)),
+            ..Default::default()
+        };
+        let transform = frames.first().copied().unwrap_or_default();
+
+        Self {
+            ghost: Ghost { frames, tick: 0 },
+            sprite: ParallaxSprite {
+                images: ParallaxImages::new("car", sprite),
+                visibility: VisibilityBundle::default(),
+                transform: TransformBundle::from_transform(transform),
+            },
+        }
+    }
+}
+
+/// Starts and stops recording as missions begin and end, and drops the run of an arrested
+/// driver
+pub fn handle_run_boundaries(
+    mut commands: Commands,
+    mut mission_events: EventReader<MissionEvent>,
+    mut arrests: EventReader<Arrested>,
+    mut recorder: ResMut<RunRecorder>,
+    mut best_runs: ResMut<BestRuns>,
+    ghost_q: Query<Entity, With<Ghost>>,
+    mode: Option<Res<GameMode>>,
+) {
+    for Arrested { car, .. } in arrests.read() {
+        if recorder.car != Some(*car) {
+            continue;
+        }
+        for ghost in ghost_q.iter() {
+            commands.entity(ghost).despawn_recursive();
+        }
+        recorder.car = None;
+        recorder.mission = None;
+        recorder.frames.clear();
+    }
+
+    for event in mission_events.read() {
+        match *event {
+            MissionEvent::Started(mission, car) => {
+                // Rides outside the story go somewhere else every time, there's nothing to race
+                if mode.as_deref().is_some_and(|mode| *mode != GameMode::Story) {
+                    continue;
+                }
+                // Keep recording another player's run
+                if recorder.car.is_some_and(|other| other != car) {
+                    continue;
+                }
+                for ghost in ghost_q.iter() {
+                    commands.entity(ghost).despawn_recursive();
+                }
+                recorder.mission = Some(mission);
+                recorder.car = Some(car);
+                recorder.frames.clear();
+                recorder.ghost_match = 0;
+
+                if let Some(best) = best_runs.by_mission.get(&mission) {
+                    commands.spawn(GhostBundle::new(best.clone()));
+                }
+            }
+            MissionEvent::Finished(mission, car) => {
+                if recorder.car != Some(car) {
+                    continue;
+                }
+                for ghost in ghost_q.iter() {
+                    commands.entity(ghost).despawn_recursive();
+                }
+
+                recorder.car = None;
+                let frames = std::mem::take(&mut recorder.frames);
+                if recorder.mission.take() != Some(mission) {
+                    continue;
+                }
+
+                let is_best = best_runs
+                    .by_mission
+                    .get(&mission)
+                    .map_or(true, |best| frames.len() < best.len());
+                if is_best {
+                    best_runs.by_mission.insert(mission, frames);
+                }
+            }
+        }
+    }
+}
+
+pub fn record_run(
+    mut recorder: ResMut<RunRecorder>,
+    car_q: Query<&Transform, (With<Car>, With<Player>)>,
+) {
+    if recorder.mission.is_none() {
+        return;
+    }
+    if let Some(Ok(transform)) = recorder.car.map(|car| car_q.get(car)) {
+        recorder.frames.push(*transform);
+    }
+}
+
+pub fn replay_ghost(mut ghost_q: Query<(&mut Ghost, &mut Transform)>) {
+    for (mut ghost, mut transform) in ghost_q.iter_mut() {
+        if let Some(frame) = ghost.frames.get(ghost.tick) {
+            *transform = *frame;
+            ghost.tick += 1;
+        }
+    }
+}
+
+/// Shows how many seconds the player is behind (+) or ahead (-) of the ghost
+pub fn show_ghost_delta(
+    mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
+    time: Res<Time<Fixed>>,
+    mut recorder: ResMut<RunRecorder>,
+    ghost_q: Query<&Ghost>,
+) {
+    let Ok(ghost) = ghost_q.get_single() else {
+        return;
+    };
+    let Some(player) = recorder.frames.last().map(|t| t.translation) else {
+        return;
+    };
+
+    // Only search forward from the previous match so that routes crossing
+    // themselves don't make the readout jump around
+    let start = recorder
+        .ghost_match
+        .min(ghost.frames.len().saturating_sub(1));
+    let end = (start + GHOST_SEARCH_WINDOW).min(ghost.frames.len());
+    let distance = |i: &usize| ghost.frames[*i].translation.distance_squared(player);
+    let closest = (start..end)
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or(start);
+    recorder.ghost_match = closest;
+
+    let delta = (recorder.frames.len() as f32 - closest as f32) * time.timestep().as_secs_f32();
+
+    let mut egui_context = egui_context.single_mut();
+    egui::Window::new("Ghost").show(egui_context.get_mut(), |ui| {
+        egui::Grid::new("ghost").show(ui, |ui| {
+            ui.label("Delta: ");
+            ui.label(format!("{delta:+.2} s"));
+        });
+    });
+}
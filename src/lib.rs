@@ -0,0 +1,33 @@
+pub mod addresses;
+pub mod appstate;
+pub mod atlas_loader;
+pub mod audio;
+pub mod autotile;
+pub mod buildings;
+pub mod camera;
+pub mod car;
+pub mod citygen;
+pub mod constants;
+pub mod daynight;
+pub mod dialogues;
+pub mod editor;
+pub mod ghost;
+pub mod missions;
+pub mod modes;
+pub mod parallax;
+pub mod piece;
+pub mod plugins;
+pub mod pointer;
+pub mod police;
+pub mod radio;
+#[cfg(test)]
+mod sim;
+pub mod tilemap;
+pub mod traffic;
+pub mod trigger;
+pub mod ui;
+pub mod utility;
+pub mod weather;
+pub mod zones;
+
+pub use plugins::{GamePlugins, GameSet};
@@ -1,45 +1,11 @@
-use crate::appstate::*;
-use crate::systems::*;
-use crate::tilemap::setup_tilemap;
-use crate::ui::*;
-use atlas_loader::setup_atlases;
 use bevy::window::WindowMode;
 use bevy::window::WindowResolution;
-use bevy::{
-    asset::AssetMetaCheck, diagnostic::FrameTimeDiagnosticsPlugin,
-    input::common_conditions::input_toggle_active, prelude::*,
-};
-use bevy_common_assets::json::JsonAssetPlugin;
+use bevy::{asset::AssetMetaCheck, prelude::*};
 use bevy_egui::EguiPlugin;
-use bevy_inspector_egui::{quick::ResourceInspectorPlugin, DefaultInspectorConfigPlugin};
 use bevy_rapier2d::prelude::*;
-use car::update_tire_forces;
-use car::{car_control, tire_friction};
-use constants::Constants;
-use dialogues::{handle_dialogue_ui, setup_dialogues, DialogueList, DialogueState};
-use missions::setup_missions;
-use missions::MissionState;
-use parallax::{ParallaxHeight, ParallaxPlugin};
-
-mod appstate;
-mod atlas_loader;
-mod buildings;
-mod car;
-mod constants;
-mod dialogues;
-mod missions;
-mod parallax;
-mod piece;
-mod pointer;
-mod road;
-mod systems;
-mod tilemap;
-mod trigger;
-mod ui;
-mod utility;
-
-use pointer::handle_pointer;
-use trigger::{handle_trigger_collisions, setup_trigger};
+use fgj_2024::{
+    appstate::AppState, editor::EditorPlugin, tilemap::MapSource, ui::DebugPlugin, GamePlugins,
+};
 
 pub fn window_primary() -> Window {
     Window {
@@ -53,6 +19,26 @@ pub fn window_primary() -> Window {
     }
 }
 
+/// `--city [seed]` plays in a generated city, with a new one every time if no seed is given
+fn map_source() -> MapSource {
+    let mut args = std::env::args().skip_while(|arg| arg != "--city");
+    if args.next().is_none() {
+        return MapSource::Level;
+    }
+    let seed = args
+        .next()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs())
+        });
+    MapSource::Generated {
+        seed,
+        size: IVec2::new(48, 48),
+    }
+}
+
 fn main() {
     let plugins = (
         DefaultPlugins
@@ -62,55 +48,22 @@ fn main() {
                 ..Default::default()
             }),
         AppState::splash_screen(),
-        FrameTimeDiagnosticsPlugin,
         RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0), //.in_fixed_schedule(),
         // RapierDebugRenderPlugin::default(),
-        //RapierDebugRenderPlugin::default(),
         EguiPlugin,
-        DefaultInspectorConfigPlugin,
-        ResourceInspectorPlugin::<Constants>::default(),
-        JsonAssetPlugin::<DialogueList>::new(&["dialogues.json"]),
-        ParallaxPlugin,
-    );
-    let update = (
-        world_inspector.run_if(input_toggle_active(false, KeyCode::F1)),
-        entity_inspector.run_if(input_toggle_active(false, KeyCode::F2)),
-        show_fps,
-        camera_follow,
-        handle_dialogue_ui,
-        handle_trigger_collisions,
-        car_control,
-        handle_pointer,
-    );
-    let startup = (
-        setup_atlases,
-        setup_graphics,
-        setup_tilemap,
-        setup_trigger,
-        setup_physics,
-        setup_buildings,
-        setup_dialogues,
-        setup_missions.after(setup_physics),
-        setup_ui.after(setup_dialogues),
+        GamePlugins,
+        DebugPlugin,
+        EditorPlugin,
     );
 
     App::new()
         .add_state::<AppState>()
         .insert_resource(AssetMetaCheck::Never)
-        .insert_resource(MissionState::default())
+        .insert_resource(map_source())
         .insert_resource(RapierConfiguration {
             gravity: Vec2::new(0., 0.),
             ..Default::default()
         })
-        .init_resource::<Constants>()
-        .register_type::<Constants>()
-        .register_type::<ParallaxHeight>()
-        .insert_resource(DialogueState::default())
         .add_plugins(plugins)
-        .add_systems(Update, update)
-        .add_systems(Startup, startup)
-        .add_systems(FixedUpdate, tire_friction)
-        .add_systems(FixedUpdate, update_tire_forces.after(tire_friction))
-        .add_systems(PostUpdate, handle_pointer)
         .run();
 }
@@ -1,10 +1,41 @@
 use bevy::prelude::*;
 
 use crate::{
+    car::Player,
     constants::Constants,
-    trigger::{Target, TriggerBundle, TriggerType},
+    daynight::WorldClock,
+    plugins::GameSet,
+    traffic::Reputation,
+    trigger::{route_trigger_collisions, Target, TriggerBundle, TriggerEntered, TriggerType},
+    weather::Weather,
 };
 
+pub struct MissionPlugin;
+
+impl Plugin for MissionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MissionState::default())
+            .init_resource::<Fares>()
+            .add_event::<MissionEvent>()
+            .add_systems(Startup, setup_missions)
+            .add_systems(
+                Update,
+                (handle_mission_triggers, hail_passengers, pay_fares)
+                    .chain()
+                    .after(route_trigger_collisions)
+                    .in_set(GameSet::Gameplay),
+            );
+    }
+}
+
+/// Sent when a player picks up or drops off a passenger, with the mission number and the car
+/// of the player
+#[derive(Event, Debug, Clone, Copy)]
+pub enum MissionEvent {
+    Started(usize, Entity),
+    Finished(usize, Entity),
+}
+
 pub const MISSION_TARGETS: [Vec2; 12] = [
     Vec2::new(16_000., 12_500.),
     Vec2::new(22_500., 21_000.),
@@ -20,53 +51,147 @@ pub const MISSION_TARGETS: [Vec2; 12] = [
     Vec2::new(11_450., 20_600.),
 ];
 
+/// What a passenger pays at the starting reputation
+pub const BASE_FARE: u32 = 40;
+/// Reputation gained for each passenger dropped off
+pub const FARE_REPUTATION: i32 = 5;
+
+/// Money earned from passengers
+#[derive(Resource, Default, Debug)]
+pub struct Fares {
+    pub total: u32,
+    /// Earned by each player, by their index
+    pub by_player: Vec<u32>,
+}
+
+impl Fares {
+    pub fn pay(&mut self, player: usize, fare: u32) {
+        self.total += fare;
+        if self.by_player.len() <= player {
+            self.by_player.resize(player + 1, 0);
+        }
+        self.by_player[player] += fare;
+    }
+}
+
+/// Hours between which the passenger of each mission is waiting for a ride,
+/// wrapping over midnight if the end is before the start
+pub const MISSION_HOURS: [(f32, f32); 6] = [
+    (0., 24.),
+    (0., 24.),
+    (19., 5.),
+    (6., 20.),
+    (21., 4.),
+    (0., 24.),
+];
+
+/// Weather each mission starts in, `None` leaves the weather as it is
+pub const MISSION_WEATHER: [Option<Weather>; 6] = [
+    None,
+    Some(Weather::Rain),
+    Some(Weather::Fog),
+    None,
+    Some(Weather::Snow),
+    None,
+];
+
+/// The chain of passengers every player competes for
 #[derive(Debug, Resource)]
 pub struct MissionState {
-    pub target_idx: Option<usize>,
+    /// Index in `targets` of the next passenger's pickup
+    pub target_idx: usize,
+    /// Where the next passenger is hailing a ride, once a player is free to pick them up
     pub current_target: Option<Vec2>,
-    pub mission_active: bool,
+    /// Pickup and drop-off points in turn, [`MISSION_TARGETS`] on the hand made map
+    pub targets: Vec<Vec2>,
 }
 
 impl Default for MissionState {
     fn default() -> Self {
-        Self {
-            target_idx: Some(0),
-            current_target: Some(MISSION_TARGETS[0]),
-            mission_active: false,
-        }
+        Self::with_targets(MISSION_TARGETS.to_vec())
     }
 }
 
 impl MissionState {
-    pub fn spawn_current_target(&self, commands: &mut Commands, constants: &Res<Constants>) {
-        let trigger_type: TriggerType = if self.mission_active {
-            TriggerType::StopMission
-        } else {
-            TriggerType::StartMission
-        };
+    pub fn with_targets(targets: Vec<Vec2>) -> Self {
+        Self {
+            target_idx: 0,
+            current_target: None,
+            targets,
+        }
+    }
 
+    /// Spawns the pickup of the next passenger, unless they are already waiting or the chain
+    /// is over
+    pub fn hail(&mut self, commands: &mut Commands, constants: &Res<Constants>) {
+        if self.current_target.is_some() {
+            return;
+        }
+        let Some(pickup) = self.targets.get(self.target_idx).copied() else {
+            return;
+        };
         commands
-            .spawn(TriggerBundle::new(trigger_type, &constants))
+            .spawn(TriggerBundle::new(TriggerType::StartMission, constants))
             .insert(Target {})
             .insert(Transform {
-                translation: self.current_target.unwrap().extend(0.),
+                translation: pickup.extend(0.),
                 ..Default::default()
             });
+        self.current_target = Some(pickup);
+    }
+
+    /// Gives a player the ride of the passenger they just picked up. Passengers outside the
+    /// chain, e.g. in free roam, get no drop-off from it
+    pub fn take_passenger(&mut self) -> Ride {
+        let ride = Ride {
+            mission: self.mission_number(),
+            dropoff: self.targets.get(self.target_idx + 1).copied(),
+        };
+        self.target_idx += 2;
+        self.current_target = None;
+        ride
     }
 
-    pub fn next_target(
-        &mut self,
-        commands: &mut Commands,
-        constants: &Res<Constants>,
-    ) -> Option<usize> {
-        let idx = self.target_idx.unwrap();
-        self.target_idx = Some(idx + 1);
-        self.mission_active = !self.mission_active;
-        self.current_target = Some(MISSION_TARGETS[idx + 1]);
-
-        self.spawn_current_target(commands, constants);
-        Some((f32::ceil(self.target_idx.unwrap() as f32 / 2.)) as usize)
+    /// Number of the next passenger's mission, starting from 1
+    pub fn mission_number(&self) -> usize {
+        self.target_idx / 2 + 1
     }
+
+    /// Hours during which the next passenger of the chain can be picked up, `None` while
+    /// nobody is hailing a ride
+    pub fn pickup_hours(&self) -> Option<(f32, f32)> {
+        self.current_target
+            .and(MISSION_HOURS.get(self.target_idx / 2).copied())
+    }
+}
+
+/// The passenger a player is driving
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Ride {
+    pub mission: usize,
+    /// Where the passenger is going, set by the game mode for passengers outside the chain
+    pub dropoff: Option<Vec2>,
+}
+
+impl Ride {
+    pub fn spawn_dropoff(&self, commands: &mut Commands, constants: &Res<Constants>, car: Entity) {
+        let Some(dropoff) = self.dropoff else {
+            return;
+        };
+        commands
+            .spawn(TriggerBundle::new(TriggerType::StopMission, constants))
+            .insert((Target {}, DropOff { car }))
+            .insert(Transform {
+                translation: dropoff.extend(0.),
+                ..Default::default()
+            });
+    }
+}
+
+/// Drop-off of the passenger riding with `car`, which the other players can't complete
+#[derive(Component, Debug, Clone, Copy)]
+pub struct DropOff {
+    pub car: Entity,
 }
 
 #[derive(Component)]
@@ -110,8 +235,96 @@ pub fn setup_missions(
     mut commands: Commands,
     // asset_server: Res<AssetServer>,
     constants: Res<Constants>,
-    state: Res<MissionState>,
+    mut state: ResMut<MissionState>,
 ) {
-    state.spawn_current_target(&mut commands, &constants);
+    state.hail(&mut commands, &constants);
     //commands.spawn(MissionStatusBundle::new(constants));
 }
+
+pub fn handle_mission_triggers(
+    mut commands: Commands,
+    mut entered: EventReader<TriggerEntered>,
+    mut mission_events: EventWriter<MissionEvent>,
+    mut mission_state: ResMut<MissionState>,
+    trigger_q: Query<(&TriggerType, Option<&DropOff>)>,
+    ride_q: Query<&Ride>,
+    constants: Res<Constants>,
+    clock: Option<Res<WorldClock>>,
+) {
+    // Triggers and cars already handled this frame, before the commands are applied
+    let mut handled: Vec<Entity> = Vec::new();
+    for TriggerEntered { trigger, by } in entered.read() {
+        let Ok((trigger_type, dropoff)) = trigger_q.get(*trigger) else {
+            continue;
+        };
+        if handled.contains(trigger) || handled.contains(by) {
+            continue;
+        }
+        match trigger_type {
+            TriggerType::StartMission => {
+                if ride_q.contains(*by) {
+                    continue;
+                }
+                // Without a clock every passenger is always waiting
+                let waiting = match (&clock, mission_state.pickup_hours()) {
+                    (Some(clock), Some(hours)) => clock.is_between(hours),
+                    _ => true,
+                };
+                if !waiting {
+                    continue;
+                }
+                commands.entity(*trigger).despawn();
+                let ride = mission_state.take_passenger();
+                ride.spawn_dropoff(&mut commands, &constants, *by);
+                commands.entity(*by).insert(ride);
+                mission_events.send(MissionEvent::Started(ride.mission, *by));
+            }
+            TriggerType::StopMission => {
+                if dropoff.map_or(true, |dropoff| dropoff.car != *by) {
+                    continue;
+                }
+                let Ok(ride) = ride_q.get(*by) else {
+                    continue;
+                };
+                commands.entity(*trigger).despawn();
+                commands.entity(*by).remove::<Ride>();
+                mission_events.send(MissionEvent::Finished(ride.mission, *by));
+            }
+            _ => continue,
+        }
+        handled.extend([*trigger, *by]);
+    }
+}
+
+/// Has the next passenger of the chain hail a ride whenever a player is free to pick them up
+pub fn hail_passengers(
+    mut commands: Commands,
+    constants: Res<Constants>,
+    mut mission_state: ResMut<MissionState>,
+    player_q: Query<Has<Ride>, With<Player>>,
+) {
+    if mission_state.current_target.is_some() || player_q.iter().all(|riding| riding) {
+        return;
+    }
+    mission_state.hail(&mut commands, &constants);
+}
+
+/// Passengers pay on drop-off, more the better the driver's reputation
+pub fn pay_fares(
+    mut mission_events: EventReader<MissionEvent>,
+    mut fares: ResMut<Fares>,
+    mut reputation: Option<ResMut<Reputation>>,
+    player_q: Query<&Player>,
+) {
+    for event in mission_events.read() {
+        let MissionEvent::Finished(_, car) = *event else {
+            continue;
+        };
+        let multiplier = reputation.as_ref().map_or(1., |r| r.fare_multiplier());
+        let fare = (BASE_FARE as f32 * multiplier).round() as u32;
+        fares.pay(player_q.get(car).map_or(0, |player| player.0), fare);
+        if let Some(reputation) = reputation.as_mut() {
+            reputation.change(FARE_REPUTATION);
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! Game modes picked from the menu after the splash screen: the story's mission chain, free
+//! roam, a time attack over checkpoints and an endless shift of random fares
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    appstate::AppState,
+    car::{Car, Player, PlayerCount, PlayerInput},
+    constants::{Constants, TILE_SIZE},
+    dialogues::handle_mission_dialogue,
+    missions::{handle_mission_triggers, Fares, MissionEvent, MissionState, Ride},
+    plugins::GameSet,
+    police::Arrested,
+    trigger::{route_trigger_collisions, Target, TriggerBundle, TriggerEntered, TriggerType},
+    utility::Rng,
+};
+
+/// Passengers hailing a ride at once in free roam
+const FREE_ROAM_HAILS: usize = 3;
+/// Checkpoints in a time attack lap
+const CHECKPOINTS: usize = 8;
+/// Seconds an endless shift lasts
+const SHIFT_SECONDS: f32 = 300.;
+/// Distance between a pickup and its drop-off at least, so every ride goes somewhere
+const MIN_RIDE_DISTANCE: f32 = 8. * TILE_SIZE;
+
+pub struct ModePlugin;
+
+impl Plugin for ModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameMode>()
+            .init_resource::<ModeState>()
+            .add_systems(OnEnter(AppState::Game), start_mode)
+            .add_systems(Startup, setup_mode_ui)
+            .add_systems(
+                Update,
+                (dispatch_fares, reach_checkpoints, run_clock)
+                    .chain()
+                    .after(route_trigger_collisions)
+                    .after(handle_mission_triggers)
+                    .before(handle_mission_dialogue)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(
+                Update,
+                (
+                    mode_menu.run_if(state_exists_and_equals(AppState::Menu)),
+                    update_mode_ui,
+                )
+                    .in_set(GameSet::Ui),
+            );
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GameMode {
+    /// The mission chain of [`MissionState`]
+    #[default]
+    Story,
+    /// No missions, any of the passengers hailing a ride can be picked up
+    FreeRoam,
+    /// A lap of checkpoints against the clock
+    TimeAttack,
+    /// Random fares one after another until the shift is over
+    EndlessShift,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [
+        GameMode::Story,
+        GameMode::FreeRoam,
+        GameMode::TimeAttack,
+        GameMode::EndlessShift,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameMode::Story => "Story",
+            GameMode::FreeRoam => "Free roam",
+            GameMode::TimeAttack => "Time attack",
+            GameMode::EndlessShift => "Endless shift",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            GameMode::Story => "Drive the passengers of the night one by one",
+            GameMode::FreeRoam => "Cruise the city and pick up whoever is hailing a ride",
+            GameMode::TimeAttack => "Drive through every checkpoint as fast as you can",
+            GameMode::EndlessShift => "Take as many fares as you can before the shift ends",
+        }
+    }
+}
+
+/// Progress in the modes other than the story
+#[derive(Resource, Debug, Default)]
+pub struct ModeState {
+    /// Curbside spots passengers hail rides from and ride to, and checkpoints are placed on
+    pub spots: Vec<Vec2>,
+    /// Checkpoints of the time attack lap in order
+    pub route: Vec<Vec2>,
+    /// Index of the next checkpoint
+    pub checkpoint: usize,
+    /// Seconds since the lap started, or left of the shift
+    pub clock: f32,
+    pub best_lap: Option<f32>,
+    /// Passengers dropped off during the shift
+    pub rides: usize,
+    pub over: bool,
+    rng: Rng,
+}
+
+impl ModeState {
+    pub fn new(spots: Vec<Vec2>, seed: u64) -> Self {
+        Self {
+            spots,
+            rng: Rng::new(seed),
+            ..default()
+        }
+    }
+
+    /// A random spot, far from `from` if there are spots far enough
+    pub fn random_spot(&mut self, from: Option<Vec2>) -> Option<Vec2> {
+        let far: Vec<Vec2> = self
+            .spots
+            .iter()
+            .copied()
+            .filter(|spot| from.map_or(true, |from| spot.distance(from) >= MIN_RIDE_DISTANCE))
+            .collect();
+        let candidates = if far.is_empty() { &self.spots } else { &far };
+        if candidates.is_empty() {
+            return None;
+        }
+        let spot = candidates[self.rng.below(candidates.len())];
+        Some(spot)
+    }
+
+    /// Starts a new lap through the level's `checkpoints` in order, or if there are none, over
+    /// random checkpoints each a ride away from the previous one
+    pub fn new_lap(&mut self, checkpoints: &[Vec2]) {
+        self.route = checkpoints.to_vec();
+        if self.route.is_empty() {
+            let mut last = None;
+            for _ in 0..CHECKPOINTS {
+                let Some(spot) = self.random_spot(last) else {
+                    break;
+                };
+                self.route.push(spot);
+                last = Some(spot);
+            }
+        }
+        self.checkpoint = 0;
+        self.clock = 0.;
+        self.over = false;
+    }
+
+    /// Ends the lap, keeping its time if it's the best one
+    pub fn finish_lap(&mut self) -> bool {
+        self.over = true;
+        let record = self.best_lap.map_or(true, |best| self.clock < best);
+        if record {
+            self.best_lap = Some(self.clock);
+        }
+        record
+    }
+}
+
+fn spawn_target(
+    commands: &mut Commands,
+    constants: &Res<Constants>,
+    trigger_type: TriggerType,
+    pos: Vec2,
+) {
+    commands
+        .spawn(TriggerBundle::new(trigger_type, constants))
+        .insert(Target {})
+        .insert(Transform {
+            translation: pos.extend(0.),
+            ..Default::default()
+        });
+}
+
+/// Checkpoint zones placed in the level, in the order of their index
+fn level_checkpoints(zone_q: &Query<(&TriggerType, &Transform), Without<Target>>) -> Vec<Vec2> {
+    let mut checkpoints: Vec<(usize, Vec2)> = zone_q
+        .iter()
+        .filter_map(|(trigger_type, transform)| match trigger_type {
+            TriggerType::Checkpoint { index } => Some((*index, transform.translation.xy())),
+            _ => None,
+        })
+        .collect();
+    checkpoints.sort_by_key(|(index, _)| *index);
+    checkpoints.into_iter().map(|(_, pos)| pos).collect()
+}
+
+/// Starts a time attack lap and puts up its first checkpoint
+fn start_lap(
+    commands: &mut Commands,
+    constants: &Res<Constants>,
+    mode_state: &mut ModeState,
+    zone_q: &Query<(&TriggerType, &Transform), Without<Target>>,
+) {
+    mode_state.new_lap(&level_checkpoints(zone_q));
+    if let Some(first) = mode_state.route.first() {
+        let checkpoint = TriggerType::Checkpoint { index: 0 };
+        spawn_target(commands, constants, checkpoint, *first);
+    }
+}
+
+/// Replaces the story's mission chain with the chosen mode once the game starts
+#[allow(clippy::too_many_arguments)]
+pub fn start_mode(
+    mut commands: Commands,
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    players: Option<Res<PlayerCount>>,
+    constants: Res<Constants>,
+    mut mission_state: ResMut<MissionState>,
+    mut mode_state: ResMut<ModeState>,
+    target_q: Query<Entity, With<Target>>,
+    ride_q: Query<Entity, With<Ride>>,
+    zone_q: Query<(&TriggerType, &Transform), Without<Target>>,
+) {
+    if *mode == GameMode::Story {
+        return;
+    }
+    for target in target_q.iter() {
+        commands.entity(target).despawn();
+    }
+    for car in ride_q.iter() {
+        commands.entity(car).remove::<Ride>();
+    }
+
+    let mut spots: Vec<Vec2> = Vec::new();
+    for target in &mission_state.targets {
+        if !spots.contains(target) {
+            spots.push(*target);
+        }
+    }
+    // However long the menu was open, as the system clock isn't available on the web
+    let seed = time.elapsed().as_nanos() as u64;
+    let best_lap = mode_state.best_lap;
+    *mode_state = ModeState::new(spots, seed);
+    mode_state.best_lap = best_lap;
+    // The modes hand out the passengers themselves
+    *mission_state = MissionState::with_targets(Vec::new());
+
+    match *mode {
+        GameMode::Story => {}
+        GameMode::FreeRoam | GameMode::EndlessShift => {
+            // Everyone has a passenger to race for during a shift
+            let players = players.map_or(1, |players| players.0);
+            let hails = if *mode == GameMode::FreeRoam {
+                FREE_ROAM_HAILS.max(players)
+            } else {
+                mode_state.clock = SHIFT_SECONDS;
+                players
+            };
+            for _ in 0..hails {
+                if let Some(spot) = mode_state.random_spot(None) {
+                    spawn_target(&mut commands, &constants, TriggerType::StartMission, spot);
+                }
+            }
+        }
+        GameMode::TimeAttack => {
+            start_lap(&mut commands, &constants, &mut mode_state, &zone_q);
+        }
+    }
+}
+
+/// Sends picked up passengers somewhere random, and has a new one hail a ride after each
+/// drop-off, or after a passenger leaves an arrested driver
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_fares(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    constants: Res<Constants>,
+    mut mission_events: EventReader<MissionEvent>,
+    mut arrests: EventReader<Arrested>,
+    mut mode_state: ResMut<ModeState>,
+    car_q: Query<&Transform, (With<Car>, With<Player>)>,
+    mut ride_q: Query<&mut Ride>,
+) {
+    if !matches!(*mode, GameMode::FreeRoam | GameMode::EndlessShift) {
+        mission_events.clear();
+        arrests.clear();
+        return;
+    }
+    for Arrested { car, mission } in arrests.read() {
+        if mission.is_none() || mode_state.over {
+            continue;
+        }
+        let pos = car_q.get(*car).ok().map(|car| car.translation.xy());
+        if let Some(spot) = mode_state.random_spot(pos) {
+            spawn_target(&mut commands, &constants, TriggerType::StartMission, spot);
+        }
+    }
+    for event in mission_events.read() {
+        match *event {
+            MissionEvent::Started(_, car) => {
+                let Ok(mut ride) = ride_q.get_mut(car) else {
+                    continue;
+                };
+                let pickup = car_q.get(car).ok().map(|car| car.translation.xy());
+                ride.dropoff = mode_state.random_spot(pickup);
+                ride.spawn_dropoff(&mut commands, &constants, car);
+            }
+            MissionEvent::Finished(_, car) => {
+                mode_state.rides += 1;
+                if mode_state.over {
+                    continue;
+                }
+                let dropoff = car_q.get(car).ok().map(|car| car.translation.xy());
+                if let Some(spot) = mode_state.random_spot(dropoff) {
+                    spawn_target(&mut commands, &constants, TriggerType::StartMission, spot);
+                }
+            }
+        }
+    }
+}
+
+/// Moves the time attack on to the next checkpoint when the current one is driven through
+pub fn reach_checkpoints(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    constants: Res<Constants>,
+    mut entered: EventReader<TriggerEntered>,
+    mut mode_state: ResMut<ModeState>,
+    checkpoint_q: Query<&TriggerType, With<Target>>,
+) {
+    if *mode != GameMode::TimeAttack {
+        entered.clear();
+        return;
+    }
+    for TriggerEntered { trigger, .. } in entered.read() {
+        let Ok(TriggerType::Checkpoint { index }) = checkpoint_q.get(*trigger) else {
+            continue;
+        };
+        if *index != mode_state.checkpoint || mode_state.over {
+            continue;
+        }
+        commands.entity(*trigger).despawn();
+        mode_state.checkpoint += 1;
+        match mode_state.route.get(mode_state.checkpoint).copied() {
+            Some(next) => {
+                let checkpoint = TriggerType::Checkpoint {
+                    index: mode_state.checkpoint,
+                };
+                spawn_target(&mut commands, &constants, checkpoint, next);
+            }
+            None => {
+                if mode_state.finish_lap() {
+                    info!("New best lap: {:.2}s", mode_state.clock);
+                }
+            }
+        }
+    }
+}
+
+/// Times the lap or counts down the shift, and restarts either with Enter once it's over
+#[allow(clippy::too_many_arguments)]
+pub fn run_clock(
+    mut commands: Commands,
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mode: Res<GameMode>,
+    constants: Res<Constants>,
+    mut mission_state: ResMut<MissionState>,
+    mut mode_state: ResMut<ModeState>,
+    players: Option<Res<PlayerCount>>,
+    target_q: Query<Entity, With<Target>>,
+    ride_q: Query<Entity, With<Ride>>,
+    zone_q: Query<(&TriggerType, &Transform), Without<Target>>,
+) {
+    match *mode {
+        GameMode::TimeAttack if !mode_state.over => mode_state.clock += time.delta_seconds(),
+        GameMode::EndlessShift if !mode_state.over => {
+            mode_state.clock = (mode_state.clock - time.delta_seconds()).max(0.);
+            if mode_state.clock > 0. {
+                return;
+            }
+            // Whoever is still waiting or riding along finds another cab
+            mode_state.over = true;
+            for target in target_q.iter() {
+                commands.entity(target).despawn();
+            }
+            for car in ride_q.iter() {
+                commands.entity(car).remove::<Ride>();
+            }
+            *mission_state = MissionState::with_targets(Vec::new());
+        }
+        GameMode::TimeAttack if keys.just_pressed(KeyCode::Return) => {
+            for target in target_q.iter() {
+                commands.entity(target).despawn();
+            }
+            start_lap(&mut commands, &constants, &mut mode_state, &zone_q);
+        }
+        GameMode::EndlessShift if keys.just_pressed(KeyCode::Return) => {
+            mode_state.over = false;
+            mode_state.rides = 0;
+            mode_state.clock = SHIFT_SECONDS;
+            for _ in 0..players.map_or(1, |players| players.0) {
+                if let Some(spot) = mode_state.random_spot(None) {
+                    spawn_target(&mut commands, &constants, TriggerType::StartMission, spot);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Mode selection shown after the splash screen
+pub fn mode_menu(
+    mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
+    mut mode: ResMut<GameMode>,
+    mut players: ResMut<PlayerCount>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Ok(mut egui_context) = egui_context.get_single_mut() else {
+        return;
+    };
+    egui::Window::new("Tango Driver")
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.get_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Players");
+                for count in 1..=PlayerInput::MAPS.len() {
+                    ui.selectable_value(&mut players.0, count, count.to_string());
+                }
+            });
+            ui.label("Arrow keys, WASD, IJKL and the numpad, one for each player");
+            ui.add_space(8.);
+            for choice in GameMode::ALL {
+                if ui.button(choice.label()).clicked() {
+                    *mode = choice;
+                    next_state.set(AppState::Game);
+                }
+                ui.label(choice.description());
+                ui.add_space(8.);
+            }
+        });
+}
+
+#[derive(Component)]
+pub struct ModeText {}
+
+pub fn setup_mode_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font,
+                font_size: constants.ui.font_size * 0.5,
+                color: constants.ui.font_color,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(constants.ui.fps_text_padding),
+            top: Val::Px(constants.ui.fps_text_padding + constants.ui.font_size),
+            ..default()
+        }),
+        ModeText {},
+    ));
+}
+
+/// Shows the lap time or what's left of the shift, and what each player has earned on a split
+/// screen
+pub fn update_mode_ui(
+    mode: Res<GameMode>,
+    mode_state: Res<ModeState>,
+    fares: Res<Fares>,
+    players: Option<Res<PlayerCount>>,
+    mut ui_q: Query<&mut Text, With<ModeText>>,
+) {
+    let mut label = match *mode {
+        GameMode::Story | GameMode::FreeRoam => String::new(),
+        GameMode::TimeAttack => {
+            let mut label = format!(
+                "Checkpoint {}/{}  {:.2}s",
+                (mode_state.checkpoint + 1).min(mode_state.route.len()),
+                mode_state.route.len(),
+                mode_state.clock
+            );
+            if let Some(best) = mode_state.best_lap {
+                label += &format!("\nBest {best:.2}s");
+            }
+            if mode_state.over {
+                label += "\nEnter to go again";
+            }
+            label
+        }
+        GameMode::EndlessShift if mode_state.over => format!(
+            "Shift over: {} rides, ${}\nEnter for another shift",
+            mode_state.rides, fares.total
+        ),
+        GameMode::EndlessShift => {
+            let seconds = mode_state.clock.ceil() as u32;
+            format!(
+                "Shift {}:{:02}  {} rides",
+                seconds / 60,
+                seconds % 60,
+                mode_state.rides
+            )
+        }
+    };
+    let players = players.map_or(1, |players| players.0);
+    if players > 1 {
+        let scores: Vec<String> = (0..players)
+            .map(|player| {
+                let earned = fares.by_player.get(player).copied().unwrap_or(0);
+                format!("P{} ${earned}", player + 1)
+            })
+            .collect();
+        if !label.is_empty() {
+            label.push('\n');
+        }
+        label += &scores.join("  ");
+    }
+    for mut text in &mut ui_q {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rides_go_somewhere() {
+        let spots = vec![Vec2::ZERO, Vec2::X * TILE_SIZE, Vec2::X * 10. * TILE_SIZE];
+        let mut state = ModeState::new(spots, 3);
+        for _ in 0..20 {
+            let spot = state.random_spot(Some(Vec2::ZERO)).unwrap();
+            assert_eq!(spot, Vec2::X * 10. * TILE_SIZE);
+        }
+        assert!(ModeState::default().random_spot(None).is_none());
+    }
+
+    #[test]
+    fn only_faster_laps_are_kept() {
+        let spots = (0..4)
+            .map(|i| Vec2::X * i as f32 * 10. * TILE_SIZE)
+            .collect();
+        let mut state = ModeState::new(spots, 1);
+        state.new_lap(&[]);
+        assert_eq!(state.route.len(), CHECKPOINTS);
+        assert!(state.route.windows(2).all(|pair| pair[0] != pair[1]));
+
+        state.clock = 60.;
+        assert!(state.finish_lap());
+        state.new_lap(&[]);
+        state.clock = 70.;
+        assert!(!state.finish_lap());
+        assert_eq!(state.best_lap, Some(60.));
+    }
+
+    #[test]
+    fn laps_go_through_the_level_checkpoints() {
+        let mut state = ModeState::new(vec![Vec2::ZERO, Vec2::X * 10. * TILE_SIZE], 1);
+        let checkpoints = [Vec2::Y, Vec2::X, Vec2::NEG_Y];
+        state.new_lap(&checkpoints);
+        assert_eq!(state.route, checkpoints);
+    }
+}
@@ -1,13 +1,34 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 
-use crate::{atlas_loader::Atlases, constants::TILE_PX_PER_UNIT};
+use crate::{
+    atlas_loader::{AtlasAnimation, Atlases},
+    camera::HudCamera,
+    constants::{TILE_PX_PER_UNIT, TILE_SIZE},
+    tilemap::Tile,
+};
+
+/// Tiles around the view that are still updated, as tall objects lean outwards from the center
+const CULL_MARGIN: i32 = 1;
+/// Length of a shadow in world units for each unit of layer height, when the light is at 45°
+const SHADOW_LENGTH: f32 = 20.;
+/// Shadows are drawn just above the ground the object stands on, below its first layer
+const SHADOW_Z: f32 = -0.01;
+const SHADOW_ALPHA: f32 = 0.35;
 
 pub struct ParallaxPlugin;
 
 impl Plugin for ParallaxPlugin {
     fn build(&self, app: &mut App) {
+        app.register_type::<ParallaxHeight>();
+        app.init_resource::<ParallaxGrid>();
+        app.init_resource::<LightDirection>();
+        app.add_systems(Update, animate_layers);
         app.add_systems(PostUpdate, add_layers);
-        app.add_systems(PostUpdate, move_layers.after(add_layers));
+        app.add_systems(PostUpdate, index_parallax_objects.after(add_layers));
+        app.add_systems(PostUpdate, move_layers.after(index_parallax_objects));
     }
 }
 
@@ -24,6 +45,8 @@ pub struct ParallaxImages {
     name: String,
     /// The fields used from the sprite are `color`, `flip_{x,y}`, `custom_size`, `anchor`
     base_sprite: Sprite,
+    /// Atlas indices of the layers, replacing the manifest's `parallax` list
+    stack: Option<Vec<i32>>,
 }
 
 impl ParallaxImages {
@@ -31,14 +54,23 @@ impl ParallaxImages {
         Self {
             name: name.into(),
             base_sprite: Default::default(),
+            stack: None,
         }
     }
     pub fn new(name: impl Into<String>, base_sprite: Sprite) -> Self {
         Self {
             name: name.into(),
             base_sprite,
+            stack: None,
         }
     }
+
+    /// Uses `stack` as the atlas indices of the layers from the bottom up, see
+    /// [`AtlasInfo::stack_layers`](crate::atlas_loader::AtlasInfo::stack_layers)
+    pub fn with_stack(mut self, stack: Vec<i32>) -> Self {
+        self.stack = Some(stack);
+        self
+    }
 }
 
 #[derive(Bundle)]
@@ -49,83 +81,381 @@ struct ParallaxLayer {
 
 #[derive(Component, Reflect)]
 pub struct ParallaxHeight {
-    pos: Vec3,
+    height: f32,
+}
+
+/// Drop shadow of a parallax object, its lowest layer darkened and moved away from the light
+#[derive(Component)]
+pub struct ParallaxShadow {
+    /// Height of the object's top layer
+    height: f32,
+}
+
+/// Direction shadows are cast in, shared by every parallax object
+#[derive(Resource)]
+pub struct LightDirection {
+    /// Shadow offset on the ground for each unit of height, 1 long when the light is at 45°
+    pub offset: Vec2,
+    /// Opacity multiplier for shadows, 0 when there is no light to cast them
+    pub strength: f32,
+}
+
+impl Default for LightDirection {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::new(0.7, -0.7),
+            strength: 1.,
+        }
+    }
+}
+
+/// Spatial hash of the parallax objects placed on map tiles
+///
+/// Tiles never move, so their objects' transforms are computed once and only the objects
+/// on tiles near the camera get their layers updated. Everything else, like cars, is updated
+/// every frame.
+#[derive(Resource, Default)]
+pub struct ParallaxGrid {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    /// Tile and global transform of each object in the grid
+    objects: HashMap<Entity, (IVec2, GlobalTransform)>,
+    /// Objects in the grid that were in view on the last frame
+    visible: HashSet<Entity>,
 }
 
+impl ParallaxGrid {
+    fn insert(&mut self, entity: Entity, tile: IVec2, transform: GlobalTransform) {
+        self.cells.entry(tile).or_default().push(entity);
+        self.objects.insert(entity, (tile, transform));
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some((tile, _)) = self.objects.remove(&entity) {
+            if let Some(cell) = self.cells.get_mut(&tile) {
+                cell.retain(|e| *e != entity);
+            }
+        }
+        self.visible.remove(&entity);
+    }
+
+    /// Objects on tiles overlapping the rectangle between `min` and `max`, in world units
+    fn objects_in(&self, min: Vec2, max: Vec2) -> HashSet<Entity> {
+        let tile = |pos: Vec2| (pos / TILE_SIZE).round().as_ivec2();
+        let (min, max) = (tile(min) - CULL_MARGIN, tile(max) + CULL_MARGIN);
+        let mut objects = HashSet::default();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                if let Some(cell) = self.cells.get(&IVec2::new(x, y)) {
+                    objects.extend(cell.iter().copied());
+                }
+            }
+        }
+        objects
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn visible_count(&self) -> usize {
+        self.visible.len()
+    }
+}
+
+/// Where a perspective camera at `camera_pos` would show a layer at `height` of an object,
+/// relative to the object in its local space
+pub fn layer_offset(object: &GlobalTransform, camera_pos: Vec3, height: f32) -> Vec3 {
+    let camera_height = camera_pos.z;
+    let scale_factor = camera_height / (camera_height - height).max(0.);
+    let world_offset = (object.translation() - camera_pos).xy() * (scale_factor - 1.);
+    to_local(object, world_offset).extend(height)
+}
+
+/// Converts a direction in world space into the local space of `object`
+fn to_local(object: &GlobalTransform, world: Vec2) -> Vec2 {
+    object
+        .affine()
+        .inverse()
+        .transform_vector3(world.extend(0.))
+        .xy()
+}
+
+/// Spawns the layers and shadow of new parallax objects, and respawns every object's layers
+/// when the atlases are reloaded
+#[allow(clippy::type_complexity)]
 pub fn add_layers(
     mut commands: Commands,
-    added_entities_without_layers: Query<(Entity, &ParallaxImages), Added<ParallaxImages>>,
-    atlases: Res<Atlases>,
+    images_q: Query<(Entity, Ref<ParallaxImages>, Option<&Children>)>,
+    layer_q: Query<(), Or<(With<ParallaxHeight>, With<ParallaxShadow>)>>,
+    atlases: Option<Res<Atlases>>,
 ) {
-    for (entity, ParallaxImages { name, base_sprite }) in added_entities_without_layers.iter() {
+    // Nothing can be spawned before the manifest has loaded
+    let Some(atlases) = atlases else {
+        return;
+    };
+    let reload = atlases.is_changed();
+    for (entity, images, children) in images_q.iter() {
+        if !reload && !images.is_added() {
+            continue;
+        }
+        for &child in children.into_iter().flatten() {
+            if layer_q.contains(child) {
+                commands.entity(child).despawn_recursive();
+            }
+        }
+
+        let ParallaxImages {
+            name,
+            base_sprite,
+            stack,
+        } = &*images;
         let Some(atlas_info) = atlases.by_name.get(name) else {
             error!("could not find atlas {name}");
             continue;
         };
         let tah = &atlas_info.atlas;
+        let stacked;
+        let layers = match stack {
+            Some(stack) => {
+                stacked = atlas_info.stack_layers(stack);
+                &stacked
+            }
+            None => &atlas_info.parallax,
+        };
         let custom_size = base_sprite
             .custom_size
             .unwrap_or(atlas_info.size / TILE_PX_PER_UNIT);
         commands.entity(entity).with_children(|parent| {
-            for (index, height) in &atlas_info.parallax {
+            for layer in layers {
                 let sprite = TextureAtlasSprite {
                     color: base_sprite.color,
-                    index: *index,
+                    index: layer.index,
                     flip_x: base_sprite.flip_x,
                     flip_y: base_sprite.flip_y,
                     custom_size: Some(custom_size),
                     anchor: base_sprite.anchor,
                 };
-                parent.spawn(ParallaxLayer {
+                let mut layer_entity = parent.spawn(ParallaxLayer {
                     sprite_bundle: SpriteSheetBundle {
                         sprite,
                         texture_atlas: tah.clone(),
                         ..Default::default()
                     },
                     height: ParallaxHeight {
-                        pos: Vec3::new(0., 0., *height),
+                        height: layer.height,
                     },
                 });
+                if let Some(animation) = &layer.animation {
+                    layer_entity.insert(animation.clone());
+                }
             }
+
+            // Flat objects like roads don't cast shadows
+            let (Some(bottom), Some(top)) = (layers.first(), layers.last()) else {
+                return;
+            };
+            if top.height <= 0. {
+                return;
+            }
+            parent.spawn((
+                SpriteSheetBundle {
+                    sprite: TextureAtlasSprite {
+                        color: Color::BLACK.with_a(0.),
+                        index: bottom.index,
+                        flip_x: base_sprite.flip_x,
+                        flip_y: base_sprite.flip_y,
+                        custom_size: Some(custom_size),
+                        anchor: base_sprite.anchor,
+                    },
+                    texture_atlas: tah.clone(),
+                    transform: Transform::from_xyz(0., 0., SHADOW_Z),
+                    ..Default::default()
+                },
+                ParallaxShadow { height: top.height },
+            ));
         });
     }
 }
+
+/// Records parallax objects placed on map tiles in [`ParallaxGrid`], hidden until they come into view
+pub fn index_parallax_objects(
+    mut grid: ResMut<ParallaxGrid>,
+    transform_helper: TransformHelper,
+    added_q: Query<(Entity, &Parent), Added<ParallaxImages>>,
+    tile_q: Query<&Tile>,
+    mut visibility_q: Query<&mut Visibility, With<ParallaxImages>>,
+    mut removed: RemovedComponents<ParallaxImages>,
+) {
+    for entity in removed.read() {
+        grid.remove(entity);
+    }
+
+    for (entity, parent) in added_q.iter() {
+        let Ok(tile) = tile_q.get(parent.get()) else {
+            continue;
+        };
+        let Ok(transform) = transform_helper.compute_global_transform(entity) else {
+            continue;
+        };
+        grid.insert(entity, tile.grid_pos(), transform);
+        if let Ok(mut visibility) = visibility_q.get_mut(entity) {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 pub fn move_layers(
-    camera: Query<Entity, With<Camera2d>>,
+    mut grid: ResMut<ParallaxGrid>,
+    light: Res<LightDirection>,
+    camera: Query<(Entity, &OrthographicProjection), (With<Camera2d>, Without<HudCamera>)>,
+    object_q: Query<(Entity, &Children), With<ParallaxImages>>,
+    mut visibility_q: Query<&mut Visibility, With<ParallaxImages>>,
     mut transform_params: ParamSet<(
-        (TransformHelper, Query<(&mut ParallaxHeight, &Parent)>),
+        TransformHelper,
         Query<(&ParallaxHeight, &mut Transform)>,
+        Query<(&ParallaxShadow, &mut Transform, &mut TextureAtlasSprite)>,
     )>,
 ) {
-    let (transform_helper, mut height_query) = transform_params.p0();
-    // Start by finding camera position
-    let camera = camera.get_single().unwrap();
-    let camera_pos = transform_helper
-        .compute_global_transform(camera)
-        .unwrap()
-        .translation();
-    let camera_height = camera_pos.z;
+    // Start by finding the camera positions, one for each player on a split screen ...
+    let transform_helper = transform_params.p0();
+    let cameras: Vec<(Vec3, Rect)> = camera
+        .iter()
+        .filter_map(|(camera, projection)| {
+            let pos = transform_helper
+                .compute_global_transform(camera)
+                .ok()?
+                .translation();
+            let area = Rect::from_corners(
+                pos.xy() + projection.area.min,
+                pos.xy() + projection.area.max,
+            );
+            Some((pos, area))
+        })
+        .collect();
+    if cameras.is_empty() {
+        return;
+    }
+    // ... and lean each object away from the camera closest to it
+    let camera_of = |object: &GlobalTransform| {
+        cameras
+            .iter()
+            .map(|(pos, _)| *pos)
+            .min_by(|a, b| {
+                let a = a.xy().distance_squared(object.translation().xy());
+                let b = b.xy().distance_squared(object.translation().xy());
+                a.total_cmp(&b)
+            })
+            .unwrap_or_default()
+    };
+
+    // ... then find the objects in view, using cached transforms for those on tiles ...
+    let mut visible = HashSet::default();
+    for (_, area) in &cameras {
+        visible.extend(grid.objects_in(area.min, area.max));
+    }
+    let mut objects: Vec<(Entity, GlobalTransform)> = visible
+        .iter()
+        .filter_map(|entity| Some((*entity, grid.objects.get(entity)?.1)))
+        .collect();
+    for (entity, _) in object_q.iter() {
+        if grid.objects.contains_key(&entity) {
+            continue;
+        }
+        if let Ok(transform) = transform_helper.compute_global_transform(entity) {
+            objects.push((entity, transform));
+        }
+    }
 
-    // ... then collect offsets to ParallaxHeight
-    for (mut height, parent) in height_query.iter_mut() {
-        let (_parent_scale, parent_rotation, parent_translation) = transform_helper
-            .compute_global_transform(parent.get())
-            .unwrap()
-            .to_scale_rotation_translation();
-        let offset = parent_translation - camera_pos;
-        // let new_pos = parent_rotation.inverse().mul_vec3(offset * height.pos.z);
-        let new_pos = parent_rotation.inverse().mul_vec3(offset);
-        height.pos.x = new_pos.x;
-        height.pos.y = new_pos.y;
-    }
-
-    // ... and finally apply transform from ParallaxHeight
-    for (height, mut transform) in transform_params.p1().iter_mut() {
-        let scale_factor = camera_height / (camera_height - height.pos.z).max(0.);
-        transform.translation = height.pos;
-        transform.translation.x *= scale_factor - 1.;
-        transform.translation.y *= scale_factor - 1.;
-        transform.scale = Vec3::new(scale_factor, scale_factor, scale_factor);
+    // ... show objects that came into view and hide those that left it ...
+    for entity in grid.visible.difference(&visible) {
+        if let Ok(mut visibility) = visibility_q.get_mut(*entity) {
+            *visibility = Visibility::Hidden;
+        }
+    }
+    for entity in visible.difference(&grid.visible) {
+        if let Ok(mut visibility) = visibility_q.get_mut(*entity) {
+            *visibility = Visibility::Inherited;
+        }
+    }
+    grid.visible = visible;
+
+    // ... offset each layer by its height ...
+    let mut layer_q = transform_params.p1();
+    for (entity, object) in &objects {
+        let Ok((_, children)) = object_q.get(*entity) else {
+            continue;
+        };
+        let camera_pos = camera_of(object);
+        for child in children {
+            let Ok((height, mut transform)) = layer_q.get_mut(*child) else {
+                continue;
+            };
+            let scale_factor = camera_pos.z / (camera_pos.z - height.height).max(0.);
+            transform.translation = layer_offset(object, camera_pos, height.height);
+            transform.scale = Vec3::splat(scale_factor);
+        }
+    }
+
+    // ... and finally cast the shadows away from the light
+    let mut shadow_q = transform_params.p2();
+    let shadow_color = Color::BLACK.with_a(SHADOW_ALPHA * light.strength);
+    for (entity, object) in &objects {
+        let Ok((_, children)) = object_q.get(*entity) else {
+            continue;
+        };
+        for child in children {
+            let Ok((shadow, mut transform, mut sprite)) = shadow_q.get_mut(*child) else {
+                continue;
+            };
+            let offset = light.offset * shadow.height * SHADOW_LENGTH;
+            transform.translation = to_local(object, offset).extend(SHADOW_Z);
+            if sprite.color != shadow_color {
+                sprite.color = shadow_color;
+            }
+        }
+    }
+}
+
+/// Advances animated layers, all layers of an object stay in step
+pub fn animate_layers(
+    time: Res<Time>,
+    mut layer_q: Query<(&mut AtlasAnimation, &mut TextureAtlasSprite)>,
+) {
+    for (mut animation, mut sprite) in layer_q.iter_mut() {
+        animation.time += time.delta_seconds();
+        let frame = animation.current_frame();
+        if sprite.index != frame {
+            sprite.index = frame;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where the layer ends up on screen, relative to the camera
+    fn projected(object: Transform, camera_pos: Vec3, height: f32) -> Vec2 {
+        let object = GlobalTransform::from(object);
+        let layer = object.transform_point(layer_offset(&object, camera_pos, height));
+        (layer - camera_pos).xy()
+    }
+
+    #[test]
+    fn layers_lean_away_from_camera_regardless_of_rotation() {
+        let camera_pos = Vec3::new(0., 0., 50.);
+        let object = Transform::from_xyz(100., 0., 0.);
+        // Halfway up to the camera everything looks twice as far from the center
+        let expected = Vec2::new(200., 0.);
+
+        for angle in [0., 0.5, std::f32::consts::PI] {
+            let rotated = object.with_rotation(Quat::from_rotation_z(angle));
+            let pos = projected(rotated, camera_pos, 25.);
+            assert!(pos.distance(expected) < 0.01, "{angle}: {pos}");
+        }
+        let scaled = object.with_scale(Vec3::new(2., 0.5, 1.));
+        let pos = projected(scaled, camera_pos, 25.);
+        assert!(pos.distance(expected) < 0.01, "{pos}");
     }
 }
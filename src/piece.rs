@@ -6,6 +6,7 @@ use bevy_rapier2d::prelude::*;
 
 use crate::constants::PX_SIZE;
 use crate::{
+    daynight::StreetLight,
     parallax::{ParallaxImages, ParallaxSprite},
     // constants::TILE_SIZE,
 };
@@ -34,6 +35,8 @@ pub struct PieceMeta {
     pub collider: Option<Collider>,
     pub anchor: Option<Anchor>,
     pub transform: Transform,
+    /// Lights up at night
+    pub street_light: bool,
 }
 
 impl PieceMeta {
@@ -42,8 +45,14 @@ impl PieceMeta {
             collider,
             transform,
             anchor,
+            street_light: false,
         }
     }
+
+    pub fn with_street_light(mut self) -> Self {
+        self.street_light = true;
+        self
+    }
 }
 
 pub fn make_lamppost(t: Transform) -> (Piece, PieceMeta) {
@@ -53,7 +62,8 @@ pub fn make_lamppost(t: Transform) -> (Piece, PieceMeta) {
             t,
             Some(Collider::cuboid(2. * PX_SIZE, 2. * PX_SIZE)),
             Some(Anchor::Custom(Vec2::new(0.27, 0.))),
-        ),
+        )
+        .with_street_light(),
     )
 }
 
@@ -62,6 +72,9 @@ pub fn spawn_as_child(cb: &mut ChildBuilder, (piece, meta): (Piece, PieceMeta))
     if let Some(collider) = meta.collider {
         f.insert(collider);
     }
+    if meta.street_light {
+        f.insert(StreetLight);
+    }
 }
 
 pub fn spawn(commands: &mut Commands, (piece, meta): (Piece, PieceMeta)) {
@@ -69,6 +82,9 @@ pub fn spawn(commands: &mut Commands, (piece, meta): (Piece, PieceMeta)) {
     if let Some(collider) = meta.collider {
         f.insert(collider);
     }
+    if meta.street_light {
+        f.insert(StreetLight);
+    }
 }
 
 impl PieceBundle {
@@ -0,0 +1,104 @@
+//! Feature plugins and the system sets they run in
+
+use bevy::{app::PluginGroupBuilder, prelude::*};
+
+use crate::{
+    addresses::AddressPlugin,
+    appstate::AppState,
+    atlas_loader::AtlasPlugin,
+    audio::GameAudioPlugin,
+    camera::CameraPlugin,
+    car::{CarPlugin, PlayerPlugin},
+    constants::Constants,
+    daynight::DayNightPlugin,
+    dialogues::DialoguePlugin,
+    ghost::GhostPlugin,
+    missions::MissionPlugin,
+    modes::ModePlugin,
+    parallax::ParallaxPlugin,
+    pointer::PointerPlugin,
+    police::PolicePlugin,
+    radio::RadioPlugin,
+    tilemap::TilemapPlugin,
+    traffic::TrafficPlugin,
+    trigger::TriggerPlugin,
+    weather::WeatherPlugin,
+    zones::ZonePlugin,
+};
+
+/// Ordering of gameplay systems within `Update`
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    /// Reading player input and applying it to the player car
+    Input,
+    /// Reacting to collisions, missions and other game rules
+    Gameplay,
+    /// Following the player with the camera
+    Camera,
+    /// Updating HUD, dialogue and debug windows
+    Ui,
+    /// Playing sounds for everything that happened during the frame
+    Audio,
+}
+
+/// Resources and system set ordering shared by every feature plugin
+pub struct CorePlugin;
+
+impl Plugin for CorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Constants>()
+            .register_type::<Constants>()
+            .configure_sets(
+                Update,
+                (
+                    GameSet::Input,
+                    GameSet::Gameplay,
+                    GameSet::Camera,
+                    GameSet::Ui,
+                    GameSet::Audio,
+                )
+                    .chain(),
+            )
+            .configure_sets(Update, GameSet::Input.run_if(playing))
+            .configure_sets(Update, GameSet::Gameplay.run_if(playing));
+    }
+}
+
+/// Whether the game is being played rather than a menu shown, always in apps without
+/// [`AppState`] such as the headless tests
+fn playing(state: Option<Res<State<AppState>>>) -> bool {
+    state.map_or(true, |state| *state.get() == AppState::Game)
+}
+
+/// Every gameplay feature of the game
+///
+/// Requires `DefaultPlugins`, `EguiPlugin` and `RapierPhysicsPlugin` to already be added.
+/// Individual features can be turned off with `GamePlugins.build().disable::<T>()`.
+pub struct GamePlugins;
+
+impl PluginGroup for GamePlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(CorePlugin)
+            .add(AtlasPlugin)
+            .add(ParallaxPlugin)
+            .add(CameraPlugin)
+            .add(TilemapPlugin)
+            .add(DayNightPlugin)
+            .add(WeatherPlugin)
+            .add(CarPlugin)
+            .add(PlayerPlugin)
+            .add(PointerPlugin)
+            .add(TriggerPlugin)
+            .add(MissionPlugin)
+            .add(ModePlugin)
+            .add(AddressPlugin)
+            .add(ZonePlugin)
+            .add(TrafficPlugin)
+            .add(PolicePlugin)
+            .add(DialoguePlugin)
+            .add(GhostPlugin)
+            .add(GameAudioPlugin)
+            .add(RadioPlugin)
+    }
+}
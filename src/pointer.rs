@@ -1,13 +1,29 @@
 use std::f32::consts::PI;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_rapier2d::plugin::PhysicsSet;
 
 use crate::{
     car::Car,
+    missions::{DropOff, Ride},
     parallax::{ParallaxImages, ParallaxSprite},
     trigger::Target,
 };
 
+pub struct PointerPlugin;
+
+impl Plugin for PointerPlugin {
+    fn build(&self, app: &mut App) {
+        // Runs after physics has moved the car, but before the arrow's global transform is computed
+        app.add_systems(
+            PostUpdate,
+            handle_pointer
+                .after(PhysicsSet::Writeback)
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
 #[derive(Component)]
 pub struct Pointer {}
 
@@ -37,23 +53,39 @@ impl PointerBundle {
     }
 }
 
+/// Points each player's arrow at the drop-off of their passenger, or at the closest passenger
+/// hailing a ride
 pub fn handle_pointer(
     mut pointer_q: Query<(&mut Transform, &Parent), With<Pointer>>,
-    car_q: Query<&GlobalTransform, (With<Car>, Without<Pointer>)>,
-    target_q: Query<&Transform, (With<Target>, Without<Pointer>)>,
+    car_q: Query<(&GlobalTransform, Has<Ride>), (With<Car>, Without<Pointer>)>,
+    target_q: Query<(&Transform, Option<&DropOff>), (With<Target>, Without<Pointer>)>,
 ) {
-    let (mut pointer_transform, parent) = pointer_q.get_single_mut().unwrap();
-    let (_, parent_rotation, parent_translation) = car_q
-        .get(parent.get())
-        .unwrap()
-        .to_scale_rotation_translation();
-
-    // Make pointer point towards target
-    if let Ok(target_transform) = target_q.get_single() {
-        let delta = target_transform.translation - parent_translation;
-
-        let angle = delta.y.atan2(delta.x) - parent_rotation.to_euler(EulerRot::XYZ).2;
-        let target_rotation = Quat::from_euler(EulerRot::XYZ, 0., 0., angle - PI / 2.);
-        pointer_transform.rotation = target_rotation;
+    for (mut pointer_transform, parent) in pointer_q.iter_mut() {
+        let Ok((car_transform, riding)) = car_q.get(parent.get()) else {
+            continue;
+        };
+        let (_, parent_rotation, parent_translation) =
+            car_transform.to_scale_rotation_translation();
+
+        // Make pointer point towards target
+        let closest = target_q
+            .iter()
+            .filter(|(_, dropoff)| match dropoff {
+                Some(dropoff) => dropoff.car == parent.get(),
+                None => !riding,
+            })
+            .map(|(transform, _)| transform)
+            .min_by(|a, b| {
+                let a = a.translation.distance_squared(parent_translation);
+                let b = b.translation.distance_squared(parent_translation);
+                a.total_cmp(&b)
+            });
+        if let Some(target_transform) = closest {
+            let delta = target_transform.translation - parent_translation;
+
+            let angle = delta.y.atan2(delta.x) - parent_rotation.to_euler(EulerRot::XYZ).2;
+            let target_rotation = Quat::from_euler(EulerRot::XYZ, 0., 0., angle - PI / 2.);
+            pointer_transform.rotation = target_rotation;
+        }
     }
 }
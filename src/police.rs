@@ -0,0 +1,366 @@
+//! Police cars chasing the player after traffic violations. The player gets away by staying out
+//! of sight or hiding in a garage, and is arrested by stopping next to a police car
+
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    car::{car_control, CarBundle, CarControls, Player},
+    constants::{Constants, TILE_SIZE},
+    missions::{DropOff, Ride},
+    plugins::GameSet,
+    tilemap::{ChunkedMap, StreamChunks},
+    traffic::{apply_penalties, PoliceAlerted, Reputation},
+    zones::{Fines, InGarage},
+};
+
+/// Wanted level at which no more police cars join the chase
+const MAX_WANTED: u32 = 3;
+/// Road tiles between the player and where police cars join the chase
+const SPAWN_TILES: u32 = 6;
+const SIGHT_RANGE: f32 = 4. * TILE_SIZE;
+/// Seconds out of sight it takes to get away
+const ESCAPE_SECS: f32 = 10.;
+/// Seconds in a garage it takes to get away
+const GARAGE_ESCAPE_SECS: f32 = 3.;
+const ARREST_DISTANCE: f32 = 300.;
+/// Speed below which the player counts as stopped by a police car next to them
+const ARREST_SPEED: f32 = 150.;
+/// Seconds stopped next to a police car before being arrested
+const ARREST_SECS: f32 = 2.;
+const ARREST_FINE: u32 = 200;
+/// Speed police cars slow down to for sharp turns
+const TURN_SPEED: f32 = 300.;
+const POLICE_COLOR: Color = Color::rgb(0.35, 0.45, 1.);
+
+const SIDES: [IVec2; 4] = [IVec2::Y, IVec2::X, IVec2::NEG_Y, IVec2::NEG_X];
+
+pub struct PolicePlugin;
+
+impl Plugin for PolicePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Chase>()
+            .add_event::<Arrested>()
+            .add_systems(Startup, setup_wanted_ui)
+            .add_systems(
+                Update,
+                drive_police.before(car_control).in_set(GameSet::Input),
+            )
+            .add_systems(
+                Update,
+                (start_chase, update_chase)
+                    .chain()
+                    .after(apply_penalties)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(Update, update_wanted_ui.in_set(GameSet::Ui));
+    }
+}
+
+#[derive(Component)]
+pub struct Police;
+
+#[derive(Resource, Default, Debug)]
+pub struct Chase {
+    /// Car being chased, `None` when nobody is
+    pub target: Option<Entity>,
+    /// How many police cars are after the target
+    pub level: u32,
+    /// Seconds since a police car last saw the target
+    pub unseen: f32,
+    /// Seconds the target has been stopped next to a police car
+    pub cornered: f32,
+}
+
+/// The player driving `car` was caught, and the passenger of `mission` left if there was one
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Arrested {
+    pub car: Entity,
+    pub mission: Option<usize>,
+}
+
+/// Steps along roads from every road tile reachable from `goal` to it. `goal` itself doesn't
+/// need to be a road
+pub fn road_distances(map: &ChunkedMap, goal: IVec2) -> HashMap<IVec2, u32> {
+    let mut distances = HashMap::new();
+    distances.insert(goal, 0);
+    let mut open = VecDeque::from([goal]);
+    while let Some(tile) = open.pop_front() {
+        let distance = distances[&tile];
+        for next in SIDES.map(|side| tile + side) {
+            let is_road = map.tile_type(next).is_some_and(|tp| tp.is_road());
+            if is_road && !distances.contains_key(&next) {
+                distances.insert(next, distance + 1);
+                open.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+/// Tile `steps` tiles closer to the goal of `distances` from `tile` along roads
+fn next_tile(distances: &HashMap<IVec2, u32>, tile: IVec2, steps: usize) -> Option<IVec2> {
+    if !distances.contains_key(&tile) {
+        return None;
+    }
+    let mut tile = tile;
+    for _ in 0..steps {
+        tile = SIDES
+            .map(|side| tile + side)
+            .into_iter()
+            .filter_map(|next| Some((next, *distances.get(&next)?)))
+            .min_by_key(|(_, distance)| *distance)
+            .filter(|(_, distance)| *distance < distances[&tile])
+            .map_or(tile, |(next, _)| next);
+    }
+    Some(tile)
+}
+
+/// Controls that turn a car facing `forward` towards `offset`, slowing down for sharp turns
+pub fn steer_towards(forward: Vec2, offset: Vec2, speed: f32) -> CarControls {
+    let angle = forward.angle_between(offset);
+    let sharp = angle.abs() > PI / 3.;
+    CarControls {
+        accelerate: !sharp || speed < TURN_SPEED,
+        brake: sharp && speed > TURN_SPEED,
+        steer: (angle / (PI / 4.)).clamp(-1., 1.),
+    }
+}
+
+fn tile_of(pos: Vec2) -> IVec2 {
+    (pos / TILE_SIZE).round().as_ivec2()
+}
+
+/// Whether nothing solid is between `from` and `to`
+fn in_sight(rapier: &RapierContext, from: Vec2, to: Vec2) -> bool {
+    let filter = QueryFilter::only_fixed().exclude_sensors();
+    from.distance(to) < SIGHT_RANGE && rapier.cast_ray(from, to - from, 1., true, filter).is_none()
+}
+
+/// Drives the police cars along the roads towards the chased car, straight at it once close.
+/// The road distances are kept until the chased car moves to another tile
+pub fn drive_police(
+    map: Option<Res<ChunkedMap>>,
+    chase: Res<Chase>,
+    mut cache: Local<Option<(IVec2, HashMap<IVec2, u32>)>>,
+    target_q: Query<&Transform, Without<Police>>,
+    mut police_q: Query<(&Transform, &Velocity, &mut CarControls), With<Police>>,
+) {
+    let Some(target) = chase.target.and_then(|car| target_q.get(car).ok()) else {
+        return;
+    };
+    let target = target.translation.xy();
+    let goal = tile_of(target);
+    if let Some(map) = &map {
+        if !cache.as_ref().is_some_and(|(tile, _)| *tile == goal) {
+            *cache = Some((goal, road_distances(map, goal)));
+        }
+    }
+    let distances = cache.as_ref().map(|(_, distances)| distances);
+
+    for (transform, velocity, mut controls) in police_q.iter_mut() {
+        let pos = transform.translation.xy();
+        let waypoint = distances
+            .and_then(|distances| next_tile(distances, tile_of(pos), 2))
+            .filter(|tile| *tile != tile_of(target))
+            .map_or(target, |tile| tile.as_vec2() * TILE_SIZE);
+        let forward = (transform.rotation * Vec3::Y).xy();
+        *controls = steer_towards(forward, waypoint - pos, velocity.linvel.length());
+    }
+}
+
+/// Sends police cars after the player on every violation while wanted, one more per violation
+pub fn start_chase(
+    mut commands: Commands,
+    mut alerts: EventReader<PoliceAlerted>,
+    mut chase: ResMut<Chase>,
+    map: Option<Res<ChunkedMap>>,
+    transform_q: Query<&Transform>,
+) {
+    for PoliceAlerted { car } in alerts.read() {
+        if chase.target.is_some_and(|target| target != *car) || chase.level >= MAX_WANTED {
+            continue;
+        }
+        let Ok(transform) = transform_q.get(*car) else {
+            continue;
+        };
+        chase.target = Some(*car);
+        chase.level += 1;
+        chase.unseen = 0.;
+        info!("wanted level {}", chase.level);
+
+        // Join the chase a few tiles down the road, or right behind the player off-road. The
+        // chunks around police cars stay loaded, so they don't drive through buildings off-screen
+        let pos = transform.translation.xy();
+        let mut tiles: Vec<(IVec2, u32)> = map
+            .as_ref()
+            .map(|map| road_distances(map, tile_of(pos)).into_iter().collect())
+            .unwrap_or_default();
+        tiles.sort_by_key(|(tile, distance)| (distance.abs_diff(SPAWN_TILES), tile.y, tile.x));
+        let spawn = tiles
+            .get(chase.level as usize - 1)
+            .map_or(pos - Vec2::Y * TILE_SIZE, |(tile, _)| {
+                tile.as_vec2() * TILE_SIZE
+            });
+        let police = CarBundle::spawn(&mut commands)
+            .with_tint(POLICE_COLOR)
+            .at(spawn)
+            .id();
+        commands
+            .entity(police)
+            .insert((Police, CarControls::default(), StreamChunks));
+    }
+}
+
+/// Lets the player get away or arrests them
+#[allow(clippy::too_many_arguments)]
+pub fn update_chase(
+    mut commands: Commands,
+    time: Res<Time>,
+    rapier: Res<RapierContext>,
+    mut chase: ResMut<Chase>,
+    mut arrests: EventWriter<Arrested>,
+    mut reputation: ResMut<Reputation>,
+    mut fines: Option<ResMut<Fines>>,
+    target_q: Query<(&Transform, &Velocity, Has<InGarage>), With<Player>>,
+    police_q: Query<(Entity, &Transform), With<Police>>,
+    ride_q: Query<&Ride>,
+    dropoff_q: Query<(Entity, &DropOff)>,
+) {
+    let Some(car) = chase.target else {
+        return;
+    };
+    let Ok((transform, velocity, hiding)) = target_q.get(car) else {
+        *chase = Chase::default();
+        return;
+    };
+    let pos = transform.translation.xy();
+    let dt = time.delta_seconds();
+
+    let seen = !hiding
+        && police_q
+            .iter()
+            .any(|(_, police)| in_sight(&rapier, police.translation.xy(), pos));
+    chase.unseen = if seen { 0. } else { chase.unseen + dt };
+
+    let cornered = police_q
+        .iter()
+        .any(|(_, police)| police.translation.xy().distance(pos) < ARREST_DISTANCE);
+    chase.cornered = if cornered && velocity.linvel.length() < ARREST_SPEED {
+        chase.cornered + dt
+    } else {
+        0.
+    };
+
+    let escape_secs = if hiding {
+        GARAGE_ESCAPE_SECS
+    } else {
+        ESCAPE_SECS
+    };
+    let arrested = chase.cornered > ARREST_SECS;
+    if !arrested && chase.unseen < escape_secs {
+        return;
+    }
+
+    if arrested {
+        // The passenger gets out, and the next fare is waiting
+        let mission = ride_q.get(car).ok().map(|ride| ride.mission);
+        if mission.is_some() {
+            for (dropoff, _) in dropoff_q.iter().filter(|(_, dropoff)| dropoff.car == car) {
+                commands.entity(dropoff).despawn_recursive();
+            }
+            commands.entity(car).remove::<Ride>();
+        }
+        if let Some(fines) = fines.as_mut() {
+            fines.total += ARREST_FINE;
+        }
+        reputation.pardon();
+        info!("arrested");
+        arrests.send(Arrested { car, mission });
+    } else {
+        info!("got away from the police");
+    }
+    for (police, _) in police_q.iter() {
+        commands.entity(police).despawn_recursive();
+    }
+    *chase = Chase::default();
+}
+
+#[derive(Component)]
+pub struct WantedText {}
+
+pub fn setup_wanted_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font,
+                font_size: constants.ui.font_size * 0.5,
+                color: Color::RED,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(45.),
+            top: Val::Px(constants.ui.fps_text_padding),
+            ..default()
+        }),
+        WantedText {},
+    ));
+}
+
+pub fn update_wanted_ui(chase: Res<Chase>, mut ui_q: Query<&mut Text, With<WantedText>>) {
+    let mut label = String::new();
+    if chase.level > 0 {
+        label = format!("WANTED {}", "*".repeat(chase.level as usize));
+        if chase.unseen > 1. {
+            label += &format!("\nLost sight {:.0}s", chase.unseen);
+        }
+    }
+    for mut text in &mut ui_q {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::TileType::{self, Grass, Road};
+
+    #[test]
+    fn police_follow_the_roads() {
+        // A U-shaped road around a field, the goal at one end
+        let mut raw: Vec<Vec<TileType>> = vec![vec![Grass; 3]; 3];
+        raw[0] = vec![Road; 3];
+        raw[1][0] = Road;
+        raw[1][2] = Road;
+        raw[2][0] = Road;
+        raw[2][2] = Road;
+        let map = ChunkedMap::new(&raw, 4);
+        let distances = road_distances(&map, IVec2::new(0, 2));
+        assert_eq!(distances[&IVec2::new(2, 2)], 6);
+        assert!(!distances.contains_key(&IVec2::new(1, 1)));
+
+        // The way round, not across the field
+        let from = IVec2::new(2, 2);
+        assert_eq!(next_tile(&distances, from, 1), Some(IVec2::new(2, 1)));
+        assert_eq!(next_tile(&distances, from, 3), Some(IVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn police_turn_towards_the_target() {
+        let left = steer_towards(Vec2::Y, Vec2::new(-1., 1.), 500.);
+        assert!(left.steer > 0. && left.accelerate);
+        let behind = steer_towards(Vec2::Y, Vec2::new(0.2, -1.), 500.);
+        assert!(behind.steer < 0. && behind.brake);
+    }
+}
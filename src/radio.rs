@@ -0,0 +1,405 @@
+//! In-car radio cycling through the music stations in `radio.stations.json`
+
+use std::time::Duration;
+
+use bevy::{
+    audio::{AddAudioSource, Source},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::{
+    audio::silent_loop,
+    constants::Constants,
+    dialogues::{DialogueEffect, DialogueState},
+    plugins::GameSet,
+};
+
+static STATIONS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/audio/radio.stations.json"
+));
+
+/// Seconds each playlist track plays before fading into the next one
+const TRACK_LENGTH: f32 = 64.;
+const SAMPLE_RATE: u32 = 22_050;
+/// How fast the music is ducked and restored, in volume per second
+const DUCK_SPEED: f32 = 2.;
+
+pub struct RadioPlugin;
+
+impl Plugin for RadioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_source::<ProceduralTrack>()
+            .add_systems(Startup, (setup_radio, setup_radio_ui))
+            .add_systems(Update, cycle_station.in_set(GameSet::Input))
+            .add_systems(
+                Update,
+                (handle_station_requests, update_radio, update_radio_ui)
+                    .chain()
+                    .in_set(GameSet::Audio),
+            );
+    }
+}
+
+#[derive(Deserialize)]
+struct StationDesc {
+    name: String,
+    kind: StationKind,
+}
+
+#[derive(Deserialize)]
+enum StationKind {
+    Playlist { tracks: Vec<String> },
+    Procedural { seed: u64, tempo: f32 },
+}
+
+enum StationSource {
+    Playlist(Vec<Handle<AudioSource>>),
+    Procedural(Handle<ProceduralTrack>),
+}
+
+struct Station {
+    name: String,
+    source: StationSource,
+}
+
+#[derive(Resource)]
+pub struct Radio {
+    stations: Vec<Station>,
+    /// `None` when the radio is switched off
+    current: Option<usize>,
+    track: usize,
+    timer: Timer,
+    /// Set when the station changed and the playing tracks need to be replaced
+    retune: bool,
+    /// Music volume multiplier, lowered while someone is talking
+    duck: f32,
+}
+
+impl Radio {
+    pub fn select(&mut self, station: Option<usize>) {
+        self.current = station;
+        self.track = 0;
+        self.timer.reset();
+        self.retune = true;
+    }
+
+    pub fn station_name(&self) -> Option<&str> {
+        self.current.map(|i| self.stations[i].name.as_str())
+    }
+}
+
+#[derive(Component)]
+pub struct MusicTrack {
+    fade_in: bool,
+    /// Fade level between 0 and 1, multiplied with the music volume
+    level: f32,
+}
+
+impl MusicTrack {
+    fn fading_in() -> Self {
+        Self {
+            fade_in: true,
+            level: 0.,
+        }
+    }
+}
+
+/// Endless synthesized music, different for each seed
+#[derive(bevy::asset::Asset, bevy::reflect::TypePath, Clone, Copy)]
+pub struct ProceduralTrack {
+    pub seed: u64,
+    /// Beats per minute
+    pub tempo: f32,
+}
+
+impl ProceduralTrack {
+    /// Four triads in MIDI notes, picked from the major scale by the seed
+    fn chords(&self) -> [[i32; 3]; 4] {
+        const SCALE: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+        const PROGRESSIONS: [[usize; 4]; 4] =
+            [[0, 4, 5, 3], [0, 5, 3, 4], [5, 3, 0, 4], [0, 3, 4, 3]];
+
+        let root = 45 + (self.seed % 12) as i32;
+        let progression = PROGRESSIONS[(self.seed / 12 % 4) as usize];
+        progression.map(|degree| {
+            [0, 2, 4].map(|step| {
+                let i = degree + step;
+                root + SCALE[i % 7] + 12 * (i / 7) as i32
+            })
+        })
+    }
+}
+
+pub struct ProceduralDecoder {
+    chords: [[i32; 3]; 4],
+    tempo: f32,
+    sample: u32,
+    /// Samples in the four bar loop, the track repeats after this
+    loop_len: u32,
+}
+
+fn frequency(note: i32) -> f32 {
+    440. * 2f32.powf((note - 69) as f32 / 12.)
+}
+
+impl Iterator for ProceduralDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        use std::f32::consts::TAU;
+
+        let t = self.sample as f32 / SAMPLE_RATE as f32;
+        self.sample = (self.sample + 1) % self.loop_len;
+
+        let beat = t * self.tempo / 60.;
+        let chord = self.chords[(beat / 4.) as usize % 4];
+        // Arpeggio on every half beat, bass note held for the whole bar
+        let step = beat * 2.;
+        let since_step = step.fract() * 30. / self.tempo;
+        let note = chord[step as usize % 3] + 12;
+
+        let lead = (TAU * frequency(note) * t).sin() * (-since_step * 8.).exp();
+        let bass = (TAU * frequency(chord[0] - 12) * t).sin();
+        Some(0.25 * lead + 0.15 * bass)
+    }
+}
+
+impl Source for ProceduralDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Decodable for ProceduralTrack {
+    type DecoderItem = f32;
+    type Decoder = ProceduralDecoder;
+
+    fn decoder(&self) -> Self::Decoder {
+        let beats = 16.;
+        ProceduralDecoder {
+            chords: self.chords(),
+            tempo: self.tempo,
+            sample: 0,
+            loop_len: ((beats * 60. / self.tempo * SAMPLE_RATE as f32) as u32).max(1),
+        }
+    }
+}
+
+pub fn setup_radio(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut procedural: ResMut<Assets<ProceduralTrack>>,
+) {
+    let descs: Vec<StationDesc> = match serde_json::from_str(STATIONS) {
+        Ok(descs) => descs,
+        Err(e) => {
+            error!("failed parsing radio.stations.json: {e}");
+            Vec::new()
+        }
+    };
+    let stations: Vec<Station> = descs
+        .into_iter()
+        .filter_map(|desc| {
+            let source = match desc.kind {
+                StationKind::Playlist { tracks } => StationSource::Playlist(
+                    tracks.into_iter().map(|t| asset_server.load(t)).collect(),
+                ),
+                StationKind::Procedural { tempo, .. } if !(tempo.is_finite() && tempo > 0.) => {
+                    error!("station {} has invalid tempo {tempo}", desc.name);
+                    return None;
+                }
+                StationKind::Procedural { seed, tempo } => {
+                    StationSource::Procedural(procedural.add(ProceduralTrack { seed, tempo }))
+                }
+            };
+            Some(Station {
+                name: desc.name,
+                source,
+            })
+        })
+        .collect();
+
+    let current = if stations.is_empty() { None } else { Some(0) };
+    commands.insert_resource(Radio {
+        stations,
+        current,
+        track: 0,
+        timer: Timer::from_seconds(TRACK_LENGTH, TimerMode::Repeating),
+        retune: true,
+        duck: 1.,
+    });
+}
+
+/// Cycles through the stations and finally switches the radio off
+pub fn cycle_station(keyboard_input: Res<Input<KeyCode>>, mut radio: ResMut<Radio>) {
+    if !keyboard_input.just_pressed(KeyCode::R) {
+        return;
+    }
+    let next = match radio.current {
+        None if !radio.stations.is_empty() => Some(0),
+        Some(i) if i + 1 < radio.stations.len() => Some(i + 1),
+        _ => None,
+    };
+    radio.select(next);
+}
+
+/// Lets passengers change the station from their dialogue
+pub fn handle_station_requests(mut effects: EventReader<DialogueEffect>, mut radio: ResMut<Radio>) {
+    for effect in effects.read() {
+        match effect {
+            DialogueEffect::RequestStation(name) => {
+                match radio.stations.iter().position(|s| &s.name == name) {
+                    Some(i) => radio.select(Some(i)),
+                    None => warn!("passenger requested unknown station {name}"),
+                }
+            }
+        }
+    }
+}
+
+pub fn update_radio(
+    mut commands: Commands,
+    time: Res<Time>,
+    constants: Res<Constants>,
+    dialogue_state: Res<DialogueState>,
+    mut radio: ResMut<Radio>,
+    mut track_q: Query<(Entity, &mut MusicTrack, Option<&AudioSink>)>,
+) {
+    let next_track = radio.timer.tick(time.delta()).just_finished()
+        && matches!(
+            radio.current.map(|i| &radio.stations[i].source),
+            Some(StationSource::Playlist(tracks)) if tracks.len() > 1
+        );
+
+    if radio.retune || next_track {
+        radio.retune = false;
+        if next_track {
+            radio.track += 1;
+        }
+        for (_, mut track, _) in track_q.iter_mut() {
+            track.fade_in = false;
+        }
+        match radio.current.map(|i| &radio.stations[i].source) {
+            Some(StationSource::Playlist(tracks)) if !tracks.is_empty() => {
+                let track = tracks[radio.track % tracks.len()].clone();
+                commands.spawn((silent_loop(track), MusicTrack::fading_in()));
+            }
+            Some(StationSource::Procedural(track)) => {
+                commands.spawn((silent_loop(track.clone()), MusicTrack::fading_in()));
+            }
+            _ => {}
+        }
+    }
+
+    let duck_target = if dialogue_state.active {
+        constants.audio.radio_duck
+    } else {
+        1.
+    };
+    let duck_step = DUCK_SPEED * time.delta_seconds();
+    radio.duck += (duck_target - radio.duck).clamp(-duck_step, duck_step);
+
+    let fade_step = time.delta_seconds() / constants.audio.music_crossfade.max(0.01);
+    for (entity, mut track, sink) in track_q.iter_mut() {
+        if track.fade_in {
+            track.level = (track.level + fade_step).min(1.);
+        } else {
+            track.level -= fade_step;
+            if track.level <= 0. {
+                commands.entity(entity).despawn();
+                continue;
+            }
+        }
+        if let Some(sink) = sink {
+            sink.set_volume(
+                constants.audio.master_volume
+                    * constants.audio.music_volume
+                    * radio.duck
+                    * track.level,
+            );
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct RadioText {}
+
+pub fn setup_radio_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    constants: Res<Constants>,
+) {
+    let font = asset_server.load("fonts/ComicMono.ttf");
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font,
+                font_size: constants.ui.font_size * 0.5,
+                color: constants.ui.font_color,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(constants.ui.fps_text_padding),
+            top: Val::Px(constants.ui.fps_text_padding),
+            ..default()
+        }),
+        RadioText {},
+    ));
+}
+
+pub fn update_radio_ui(radio: Res<Radio>, mut ui_q: Query<&mut Text, With<RadioText>>) {
+    let label = match radio.station_name() {
+        Some(name) => format!("Radio: {name}"),
+        None => String::from("Radio off"),
+    };
+    for mut text in &mut ui_q {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_stations_have_something_to_play() {
+        let stations: Vec<StationDesc> = serde_json::from_str(STATIONS).unwrap();
+        assert!(!stations.is_empty());
+        for station in stations {
+            match station.kind {
+                StationKind::Playlist { tracks } => {
+                    assert!(!tracks.is_empty(), "{} has no tracks", station.name);
+                    for track in tracks {
+                        let path = format!("{}/assets/{track}", env!("CARGO_MANIFEST_DIR"));
+                        assert!(
+                            std::path::Path::new(&path).is_file(),
+                            "{} plays missing {track}",
+                            station.name
+                        );
+                    }
+                }
+                StationKind::Procedural { tempo, .. } => {
+                    assert!(tempo > 0., "{} has no tempo", station.name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Headless app for exercising gameplay systems in tests
+//!
+//! Runs with `MinimalPlugins`, Rapier and the gameplay plugins that don't render
+//! anything, so no window or GPU is needed.
+//! Every call to [`HeadlessApp::run_ticks`] advances exactly one fixed timestep per tick.
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    car::{CarBundle, CarPlugin},
+    missions::MissionPlugin,
+    plugins::CorePlugin,
+    trigger::TriggerPlugin,
+};
+
+pub struct HeadlessApp {
+    pub app: App,
+}
+
+impl HeadlessApp {
+    pub fn new() -> Self {
+        let mut app = App::new();
+        let timestep = Time::<Fixed>::default().timestep();
+
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
+            CorePlugin,
+            CarPlugin,
+            TriggerPlugin,
+            MissionPlugin,
+        ))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(timestep))
+        .insert_resource(RapierConfiguration {
+            gravity: Vec2::new(0., 0.),
+            ..Default::default()
+        })
+        .init_resource::<Input<KeyCode>>();
+
+        Self { app }
+    }
+
+    pub fn spawn_player(&mut self, pos: Vec2) -> Entity {
+        self.app
+            .world
+            .run_system_once(move |mut commands: Commands| {
+                CarBundle::spawn(&mut commands).with_player(0).at(pos).id()
+            })
+    }
+
+    pub fn spawn_car(&mut self, pos: Vec2) -> Entity {
+        self.app
+            .world
+            .run_system_once(move |mut commands: Commands| {
+                CarBundle::spawn(&mut commands).at(pos).id()
+            })
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.app.world.resource_mut::<Input<KeyCode>>().press(key);
+    }
+
+    pub fn release(&mut self, key: KeyCode) {
+        self.app.world.resource_mut::<Input<KeyCode>>().release(key);
+    }
+
+    pub fn run_ticks(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.app.update();
+        }
+    }
+
+    pub fn transform(&self, entity: Entity) -> Transform {
+        *self.app.world.get::<Transform>(entity).unwrap()
+    }
+
+    pub fn resource<R: Resource>(&self) -> &R {
+        self.app.world.resource::<R>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::TILE_SIZE,
+        missions::{MissionState, Ride, MISSION_TARGETS},
+        tilemap::{stream_chunks, Chunk, ChunkedMap, StreamChunks, TileType},
+        trigger::{Target, TriggerShape, TriggerType},
+        zones::{Fines, InGarage, ZoneDesc, ZonePlugin},
+    };
+
+    #[test]
+    fn driving_forward() {
+        let mut sim = HeadlessApp::new();
+        let car = sim.spawn_player(Vec2::ZERO);
+
+        sim.press(KeyCode::Up);
+        sim.run_ticks(90);
+        sim.release(KeyCode::Up);
+        sim.run_ticks(30);
+
+        let transform = sim.transform(car);
+        assert!(transform.translation.y > 100., "{transform:?}");
+        assert!(
+            transform.translation.x.abs() < 0.05 * transform.translation.y,
+            "{transform:?}"
+        );
+    }
+
+    #[test]
+    fn steering_left() {
+        let mut sim = HeadlessApp::new();
+        let car = sim.spawn_player(Vec2::ZERO);
+
+        sim.press(KeyCode::Up);
+        sim.press(KeyCode::Left);
+        sim.run_ticks(120);
+
+        let transform = sim.transform(car);
+        let angle = transform.rotation.to_euler(EulerRot::XYZ).2;
+        assert!(angle > 0.1, "{transform:?}");
+        assert!(transform.translation.x < 0., "{transform:?}");
+    }
+
+    #[test]
+    fn completing_mission_trigger() {
+        let mut sim = HeadlessApp::new();
+        let car = sim.spawn_player(MISSION_TARGETS[0]);
+
+        sim.run_ticks(3);
+
+        let ride = sim.app.world.get::<Ride>(car).copied();
+        assert_eq!(ride.and_then(|ride| ride.dropoff), Some(MISSION_TARGETS[1]));
+        assert_eq!(sim.resource::<MissionState>().target_idx, 2);
+
+        let targets: Vec<Vec3> = sim
+            .app
+            .world
+            .query_filtered::<&Transform, With<Target>>()
+            .iter(&sim.app.world)
+            .map(|t| t.translation)
+            .collect();
+        assert_eq!(targets, vec![MISSION_TARGETS[1].extend(0.)]);
+    }
+
+    #[test]
+    fn other_cars_do_not_complete_mission_trigger() {
+        let mut sim = HeadlessApp::new();
+        let player = sim.spawn_player(Vec2::ZERO);
+        sim.spawn_car(MISSION_TARGETS[0]);
+
+        sim.run_ticks(3);
+
+        assert!(sim.app.world.get::<Ride>(player).is_none());
+        assert_eq!(sim.resource::<MissionState>().target_idx, 0);
+    }
+
+    #[test]
+    fn speeding_through_trap_and_parking_in_garage() {
+        let mut sim = HeadlessApp::new();
+        sim.app.add_plugins(ZonePlugin);
+        let car = sim.spawn_player(Vec2::ZERO);
+        let zones = [
+            ZoneDesc {
+                trigger: TriggerType::SpeedTrap {
+                    limit: 50.,
+                    fine: 50,
+                },
+                shape: TriggerShape::Rectangle {
+                    half_size: Vec2::new(300., 50.),
+                },
+                position: Vec2::new(0., 300.),
+                rotation: 0.,
+            },
+            ZoneDesc {
+                trigger: TriggerType::Garage,
+                shape: TriggerShape::Rectangle {
+                    half_size: Vec2::splat(400.),
+                },
+                position: Vec2::new(0., 1200.),
+                rotation: 0.,
+            },
+        ];
+        sim.app
+            .world
+            .run_system_once(move |mut commands: Commands| {
+                for zone in &zones {
+                    zone.spawn(&mut commands);
+                }
+            });
+
+        sim.press(KeyCode::Up);
+        for _ in 0..600 {
+            if sim.transform(car).translation.y > 400. {
+                break;
+            }
+            sim.run_ticks(1);
+        }
+        assert_eq!(sim.resource::<Fines>().total, 50);
+        assert!(sim.app.world.get::<InGarage>(car).is_none());
+
+        for _ in 0..600 {
+            if sim.app.world.get::<InGarage>(car).is_some() {
+                break;
+            }
+            sim.run_ticks(1);
+        }
+        sim.release(KeyCode::Up);
+        assert!(sim.app.world.get::<InGarage>(car).is_some());
+        assert_eq!(sim.resource::<Fines>().total, 50);
+    }
+
+    #[test]
+    fn chunks_stream_around_the_camera() {
+        let mut sim = HeadlessApp::new();
+        // 10x10 chunks of the default 4x4 tiles, streamed 1 chunk around the camera
+        let tiles = vec![vec![TileType::Grass; 40]; 40];
+        sim.app
+            .insert_resource(ChunkedMap::new(&tiles, 4))
+            .add_systems(Update, stream_chunks);
+        let camera = sim
+            .app
+            .world
+            .spawn((Camera2d::default(), Transform::default()))
+            .id();
+        let look_at = |sim: &mut HeadlessApp, tile: Vec2| {
+            sim.app
+                .world
+                .get_mut::<Transform>(camera)
+                .unwrap()
+                .translation = (tile * TILE_SIZE).extend(0.);
+            sim.run_ticks(1);
+            let chunks = sim.app.world.query::<&Chunk>().iter(&sim.app.world).count();
+            let loaded = sim.resource::<ChunkedMap>().loaded_count();
+            assert_eq!(chunks, loaded);
+            loaded
+        };
+
+        // Only the corner of the map is around the camera
+        assert_eq!(look_at(&mut sim, Vec2::new(2., 2.)), 4);
+        assert_eq!(look_at(&mut sim, Vec2::new(22., 22.)), 9);
+        // The chunks left behind are kept for a chunk more
+        assert_eq!(look_at(&mut sim, Vec2::new(26., 22.)), 12);
+        assert_eq!(look_at(&mut sim, Vec2::new(22., 22.)), 12);
+        assert_eq!(look_at(&mut sim, Vec2::new(30., 22.)), 12);
+
+        // Police cars off-screen keep the chunks around them loaded too
+        let far = Transform::from_translation((Vec2::splat(38.) * TILE_SIZE).extend(0.));
+        sim.app.world.spawn((StreamChunks, far));
+        assert_eq!(look_at(&mut sim, Vec2::new(30., 22.)), 16);
+    }
+}
@@ -1,31 +1,190 @@
-use bevy::prelude::*;
+use std::{f32::consts::PI, fmt};
+
+use bevy::{prelude::*, utils::HashMap};
 use bevy_rapier2d::prelude::Collider;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::TILE_SIZE,
+    addresses::AddressBook,
+    autotile::{AutotileRules, RoadNeighbors},
+    buildings::{spawn_building, Building, BuildingBlock, BuildingPalette},
+    camera::HudCamera,
+    citygen::City,
+    constants::{Constants, MapConstants, PX_SIZE, TILE_SIZE},
+    missions::MissionState,
     piece::*,
-    road::{Road, RoadBundle},
+    plugins::GameSet,
 };
 
+pub struct TilemapPlugin;
+
+impl Plugin for TilemapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MapSource>()
+            .add_systems(PreStartup, load_city)
+            .add_systems(Startup, setup_tilemap)
+            .add_systems(Update, stream_chunks.in_set(GameSet::Camera));
+    }
+}
+
+/// Where the city comes from, set before the app starts
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub enum MapSource {
+    /// The hand made `Level_0` map
+    #[default]
+    Level,
+    Generated {
+        seed: u64,
+        size: IVec2,
+    },
+}
+
+/// Tiles of the city being played
+#[derive(Resource)]
+pub struct CityMap {
+    /// Rows of tiles from the bottom up
+    pub tiles: Vec<Vec<TileType>>,
+    /// Where the player starts, `None` on the hand made map
+    pub start: Option<Vec2>,
+    pub placements: Placements,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    /// Counterclockwise angle from north
+    pub fn angle(&self) -> f32 {
+        use Direction::*;
+        let quarters = match self {
+            North => 0.,
+            West => 1.,
+            South => 2.,
+            East => 3.,
+        };
+        quarters * PI / 2.
+    }
+
+    pub fn as_vec2(&self) -> Vec2 {
+        Vec2::from_angle(self.angle()).rotate(Vec2::Y)
+    }
+
+    /// Direction closest to `v`
+    pub fn nearest(v: Vec2) -> Self {
+        use Direction::*;
+        match (v.x.abs() > v.y.abs(), v.x > 0., v.y > 0.) {
+            (true, true, _) => East,
+            (true, false, _) => West,
+            (false, _, true) => North,
+            (false, _, false) => South,
+        }
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        matches!(self, Direction::North | Direction::South)
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum TileType {
     Empty,
     Grass,
     Road,
     Building,
+    /// Blocks cars, unless bridged
+    Water,
+    /// Drivable lot with marked stalls
+    Parking,
+    /// Grass with trees to drive around
+    Park,
+    /// Road over water, with railings on the sides
+    Bridge,
+    /// Road that should only be driven in one direction
+    OneWay(Direction),
 }
 
-impl From<i32> for TileType {
-    fn from(value: i32) -> Self {
+/// IntGrid value in the map that isn't any [`TileType`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownTile(pub i32);
+
+impl fmt::Display for UnknownTile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported tile number {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownTile {}
+
+impl TryFrom<i32> for TileType {
+    type Error = UnknownTile;
+
+    /// Converts the IntGrid values of the map layer in `tilemap.ldtk`
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
         use TileType::*;
-        match value {
-            0 => Grass,
+        Ok(match value {
+            0 => Empty,
             1 => Grass,
             2 => Road,
             3 => Building,
-            _ => panic!("unsupported tile number {value}"),
+            4 => Water,
+            5 => Parking,
+            6 => Park,
+            7 => Bridge,
+            8 => OneWay(Direction::North),
+            9 => OneWay(Direction::East),
+            10 => OneWay(Direction::South),
+            11 => OneWay(Direction::West),
+            _ => return Err(UnknownTile(value)),
+        })
+    }
+}
+
+impl From<TileType> for i32 {
+    fn from(tp: TileType) -> Self {
+        use TileType::*;
+        match tp {
+            Empty => 0,
+            Grass => 1,
+            Road => 2,
+            Building => 3,
+            Water => 4,
+            Parking => 5,
+            Park => 6,
+            Bridge => 7,
+            OneWay(Direction::North) => 8,
+            OneWay(Direction::East) => 9,
+            OneWay(Direction::South) => 10,
+            OneWay(Direction::West) => 11,
+        }
+    }
+}
+
+impl TileType {
+    /// Part of the road network, connected to other roads by the autotiler
+    pub fn is_road(&self) -> bool {
+        matches!(
+            self,
+            TileType::Road | TileType::Bridge | TileType::OneWay(_)
+        )
+    }
+
+    /// Direction of traffic on one-way roads
+    pub fn one_way(&self) -> Option<Direction> {
+        match self {
+            TileType::OneWay(direction) => Some(*direction),
+            _ => None,
         }
     }
+
+    /// Whether a tile of this type next to `other` is joined to it, rather than a separate area
+    fn connects(&self, other: &TileType) -> bool {
+        self == other || (self.is_road() && other.is_road())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -61,6 +220,28 @@ pub struct Tile {
     tp: TileType,
     pos: Vec2,
     neighbors: Neighbors,
+    /// Whether the north-east, south-east, south-west and north-west neighbours are the same type
+    corners: [bool; 4],
+}
+
+impl Tile {
+    /// Column and row of the tile in the map
+    pub fn grid_pos(&self) -> IVec2 {
+        self.pos.as_ivec2()
+    }
+
+    pub fn tile_type(&self) -> TileType {
+        self.tp
+    }
+
+    /// Whether the north, east, south and west neighbours connect to this tile
+    pub fn sides(&self) -> [bool; 4] {
+        self.neighbors.as_array()
+    }
+
+    pub fn corners(&self) -> [bool; 4] {
+        self.corners
+    }
 }
 
 #[derive(Bundle)]
@@ -73,9 +254,7 @@ impl TileBundle {
     fn new(tile: Tile) -> Self {
         TileBundle {
             spatial: SpatialBundle {
-                transform: dbg!(Transform::from_translation(
-                    (tile.pos * TILE_SIZE).extend(-0.1)
-                )),
+                transform: Transform::from_translation((tile.pos * TILE_SIZE).extend(-0.1)),
                 ..Default::default()
             },
             tile,
@@ -83,77 +262,99 @@ impl TileBundle {
     }
 }
 
-fn try_spawn_pavement(
-    cb: &mut ChildBuilder,
-    tile: &Tile,
-    tr: impl Fn(Transform) -> Transform,
-) -> Result<(), ()> {
-    match tile.neighbors.as_array() {
-        [true, true, true, true] => {
-            cb.spawn(RoadBundle::new(
-                Road::new("pavement_cross"),
-                tr(Transform::default()),
-            ));
-            Ok(())
-        }
-        [false, true, true, true] => {
-            cb.spawn(RoadBundle::new(
-                Road::new("pavement_t"),
-                tr(Transform::default()),
-            ));
-            Ok(())
-        }
-        [_, false, true, false] => {
-            for dy in [-TILE_SIZE / 3., 0., TILE_SIZE / 3.] {
-                cb.spawn(RoadBundle::new(
-                    Road::new("pavement"),
-                    tr(Transform::from_xyz(0., dy, 0.)),
-                ));
-                for dx in [-TILE_SIZE / 3., TILE_SIZE / 3.] {
-                    spawn_as_child(
-                        cb,
-                        (
-                            Piece::new("sidewalk"),
-                            PieceMeta::new(tr(Transform::from_xyz(dx, dy, 0.5)), None, None),
-                        ),
-                    );
-
-                    let ninety = std::f32::consts::PI / 2.;
-                    // let quat = Quat::from_rotation_z(dx.signum() * ninety);
-                    let mut t = Transform::from_xyz(dx, dy, 0.6);
-                    t.rotate_z(-dx.signum() * ninety);
-                    spawn_as_child(cb, (Piece::new("curb"), PieceMeta::new(tr(t), None, None)));
-                }
-            }
-            let parity = ((tile.pos.x + tile.pos.y) as i32).rem_euclid(2);
-            if parity == 0 {
-                spawn_as_child(
-                    cb,
-                    make_lamppost(tr(Transform::from_xyz(TILE_SIZE / 3., 0., 0.))),
-                );
-            }
-            if parity == 1 {
-                let mut t = Transform::from_xyz(-TILE_SIZE / 3., 0., 0.);
-                t.rotate_z(std::f32::consts::PI);
-                spawn_as_child(cb, make_lamppost(tr(t)));
-            }
-            Ok(())
+/// Data driven piece sets, loaded once for all tiles
+struct PieceSets {
+    palette: BuildingPalette,
+    roads: AutotileRules,
+}
+
+fn spawn_road(cb: &mut ChildBuilder, tile: &Tile, sets: &PieceSets) {
+    let neighbors = RoadNeighbors {
+        sides: tile.neighbors.as_array(),
+        corners: tile.corners,
+    };
+    if !sets.roads.spawn(cb, tile.grid_pos(), &neighbors) {
+        warn!("no road rule for tile {}", tile.grid_pos());
+    }
+}
+
+fn spawn_water(cb: &mut ChildBuilder, collider: Option<Collider>) {
+    spawn_as_child(
+        cb,
+        (
+            Piece::new("water"),
+            PieceMeta::new(Transform::from_xyz(0., 0., -0.5), collider, None),
+        ),
+    );
+}
+
+/// Bridge deck along the road, with railings on the sides the road doesn't continue to
+fn spawn_bridge(cb: &mut ChildBuilder, tile: &Tile) {
+    spawn_water(cb, None);
+    let n = tile.neighbors;
+    let along_x = (n.east || n.west) && !(n.north || n.south);
+    let deck_rotation = Quat::from_rotation_z(if along_x { PI / 2. } else { 0. });
+    spawn_as_child(
+        cb,
+        (
+            Piece::new("bridge"),
+            PieceMeta::new(Transform::from_rotation(deck_rotation), None, None),
+        ),
+    );
+
+    let railing_width = 8. * PX_SIZE;
+    // South, east, north and west, the order the railing is rotated to
+    for (rot_count, open) in [n.south, n.east, n.north, n.west].into_iter().enumerate() {
+        if open {
+            continue;
         }
-        [false, true, true, false] => {
-            spawn_as_child(
-                cb,
-                (
-                    Piece::new("pavement_turn"),
-                    PieceMeta::new(tr(Transform::default()), None, None),
+        let mut transform = Transform::from_xyz(0., railing_width - TILE_SIZE / 2., 0.5);
+        transform.rotate_around(
+            Vec3::ZERO,
+            Quat::from_rotation_z(rot_count as f32 * PI / 2.),
+        );
+        spawn_as_child(
+            cb,
+            (
+                Piece::new("railing"),
+                PieceMeta::new(
+                    transform,
+                    Some(Collider::cuboid(TILE_SIZE / 2., railing_width)),
+                    None,
                 ),
-            );
-            Ok(())
-        }
-        _ => Err(()),
+            ),
+        );
     }
 }
 
-fn spawn_pieces(cb: &mut ChildBuilder, tile: &Tile) {
+/// A few trees at spots picked by the tile position
+fn spawn_park(cb: &mut ChildBuilder, tile: &Tile) {
+    let pos = tile.grid_pos();
+    let flip = if (pos.x + pos.y).rem_euclid(2) == 0 {
+        1.
+    } else {
+        -1.
+    };
+    for (x, y) in [(-1., -1.), (1., -0.3), (-0.4, 1.)] {
+        let offset = Vec2::new(x * flip, y) * TILE_SIZE / 3.;
+        spawn_as_child(
+            cb,
+            (
+                Piece::new("tree"),
+                PieceMeta::new(
+                    Transform::from_translation(offset.extend(0.5)),
+                    Some(Collider::ball(6. * PX_SIZE)),
+                    None,
+                ),
+            ),
+        );
+    }
+}
+
+fn spawn_pieces(cb: &mut ChildBuilder, tile: &Tile, sets: &PieceSets, building: Option<&Building>) {
+    if tile.tp == TileType::Empty {
+        return;
+    }
     spawn_as_child(
         cb,
         (
@@ -162,97 +363,483 @@ fn spawn_pieces(cb: &mut ChildBuilder, tile: &Tile) {
         ),
     );
     match tile.tp {
-        TileType::Road => {
-            for rot_count in 0..4 {
-                let rotated_tile = Tile {
-                    neighbors: tile.neighbors.rotate_ccw(-rot_count),
-                    ..tile.clone()
-                };
-                let angle = rot_count as f32 * std::f32::consts::PI / 2.;
-                let quat = Quat::from_rotation_z(angle);
-                let tr = |mut transform: Transform| {
-                    transform.rotate_around(Vec3::ZERO, quat);
-                    transform
-                };
-                if let Ok(_) = try_spawn_pavement(cb, &rotated_tile, tr) {
-                    break;
+        TileType::Road => spawn_road(cb, tile, sets),
+        TileType::OneWay(direction) => {
+            spawn_road(cb, tile, sets);
+            spawn_as_child(
+                cb,
+                (
+                    Piece::new("oneway_arrow"),
+                    PieceMeta::new(
+                        Transform::from_xyz(0., 0., 0.1)
+                            .with_rotation(Quat::from_rotation_z(direction.angle())),
+                        None,
+                        None,
+                    ),
+                ),
+            );
+        }
+        TileType::Bridge => spawn_bridge(cb, tile),
+        TileType::Water => {
+            spawn_water(cb, Some(Collider::cuboid(TILE_SIZE / 2., TILE_SIZE / 2.)));
+        }
+        TileType::Parking => {
+            spawn_as_child(
+                cb,
+                (
+                    Piece::new("parking"),
+                    PieceMeta::new(Transform::default(), None, None),
+                ),
+            );
+        }
+        TileType::Park => spawn_park(cb, tile),
+        TileType::Building => {
+            let Some(building) = building else {
+                return;
+            };
+            // Walls only on the outline of the block
+            let n = tile.neighbors;
+            let outline = [!n.south, !n.east, !n.north, !n.west];
+            spawn_building(cb, &sets.palette, building, outline);
+        }
+        _ => {}
+    }
+}
+
+/// Path of the level placements relative to the crate, for the editor to export to
+pub const PLACEMENTS_PATH: &str = "assets/tilemap/simplified/Level_0/placements.json";
+
+static PLACEMENTS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/tilemap/simplified/Level_0/placements.json"
+));
+
+/// A piece placed freely on the hand made map instead of coming from a tile
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PiecePlacement {
+    pub atlas: String,
+    pub position: Vec2,
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl PiecePlacement {
+    pub fn spawn(&self, commands: &mut Commands) {
+        let transform = Transform::from_translation(self.position.extend(0.))
+            .with_rotation(Quat::from_rotation_z(self.rotation));
+        spawn(
+            commands,
+            (
+                Piece::new(&self.atlas),
+                PieceMeta::new(transform, None, None),
+            ),
+        );
+    }
+}
+
+/// Everything on the hand made map that isn't a tile or a zone
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Placements {
+    pub pieces: Vec<PiecePlacement>,
+    /// Mission pickups and drop-offs, replacing [`crate::missions::MISSION_TARGETS`] if not empty
+    pub targets: Vec<Vec2>,
+}
+
+impl Placements {
+    pub fn load() -> Self {
+        match serde_json::from_str(PLACEMENTS) {
+            Ok(placements) => placements,
+            Err(e) => {
+                error!("failed parsing placements.json: {e}");
+                Self::default()
+            }
+        }
+    }
+}
+
+/// Loads or generates the city and its street names, and moves the missions to it
+pub fn load_city(
+    mut commands: Commands,
+    source: Res<MapSource>,
+    mission_state: Option<ResMut<MissionState>>,
+) {
+    let map = match *source {
+        MapSource::Level => {
+            let placements = Placements::load();
+            if let Some(mut mission_state) = mission_state {
+                if !placements.targets.is_empty() {
+                    *mission_state = MissionState::with_targets(placements.targets.clone());
                 }
             }
+            commands.insert_resource(AddressBook::load());
+            CityMap {
+                tiles: load_map(),
+                start: None,
+                placements,
+            }
         }
-        TileType::Building => {
-            for rot_count in 0..4 {
-                let rotated_tile = Tile {
-                    neighbors: tile.neighbors.rotate_ccw(-rot_count),
-                    ..tile.clone()
+        MapSource::Generated { seed, size } => {
+            info!("generating a {}x{} city with seed {seed}", size.x, size.y);
+            let city = City::generate(seed, size);
+            commands.insert_resource(AddressBook::generate(&city.tiles, seed));
+            if let Some(mut mission_state) = mission_state {
+                *mission_state = MissionState::with_targets(city.targets);
+            }
+            CityMap {
+                tiles: city.tiles,
+                start: Some(city.start),
+                placements: Placements::default(),
+            }
+        }
+    };
+    commands.insert_resource(map);
+}
+
+/// Map tiles with everything needed to spawn them, spawned in chunks around the camera
+#[derive(Resource)]
+pub struct ChunkedMap {
+    /// Rows of tile types from the bottom up, as loaded or edited
+    raw: Vec<Vec<TileType>>,
+    tiles: HashMap<IVec2, Tile>,
+    /// Building style of each building tile, shared by its whole block
+    buildings: HashMap<IVec2, Building>,
+    sets: PieceSets,
+    /// Width and height of a chunk in tiles
+    chunk_size: i32,
+    /// Entity of each spawned chunk, its tiles are its children
+    loaded: HashMap<IVec2, Entity>,
+}
+
+/// Parent of the tiles and building colliders in one chunk of the map
+#[derive(Component, Debug)]
+pub struct Chunk {
+    pub pos: IVec2,
+}
+
+impl ChunkedMap {
+    pub fn new(raw_map: &[Vec<TileType>], chunk_size: i32) -> Self {
+        let sets = PieceSets {
+            palette: BuildingPalette::load(),
+            roads: AutotileRules::load(),
+        };
+        let mut map = Self {
+            raw: raw_map.to_vec(),
+            tiles: HashMap::new(),
+            buildings: HashMap::new(),
+            sets,
+            chunk_size: chunk_size.max(1),
+            loaded: HashMap::new(),
+        };
+        map.rebuild();
+        map
+    }
+
+    /// Recomputes the neighbours of every tile and the building blocks from `raw`
+    fn rebuild(&mut self) {
+        let raw_map = &self.raw;
+        let mut tiles = HashMap::new();
+        for (i, row) in raw_map.iter().enumerate() {
+            for (j, &tp) in row.iter().enumerate() {
+                // Tiles outside the map never match
+                let same = |di: isize, dj: isize| {
+                    let (Some(i), Some(j)) = (i.checked_add_signed(di), j.checked_add_signed(dj))
+                    else {
+                        return false;
+                    };
+                    raw_map
+                        .get(i)
+                        .and_then(|row| row.get(j))
+                        .is_some_and(|other| tp.connects(other))
                 };
-                let angle = rot_count as f32 * std::f32::consts::PI / 2.;
-                let quat = Quat::from_rotation_z(angle);
-                let tr = |mut transform: Transform| {
-                    transform.rotate_around(Vec3::ZERO, quat);
-                    transform
+                let tile = Tile {
+                    tp,
+                    pos: Vec2::new(j as f32, i as f32),
+                    neighbors: Neighbors {
+                        north: same(1, 0),
+                        east: same(0, 1),
+                        south: same(-1, 0),
+                        west: same(0, -1),
+                    },
+                    corners: [same(1, 1), same(-1, 1), same(-1, -1), same(1, -1)],
                 };
-                spawn_as_child(
-                    cb,
-                    (
-                        Piece::new("building2"),
-                        PieceMeta::new(
-                            tr(Transform::default()),
-                            Some(Collider::cuboid(TILE_SIZE / 2., TILE_SIZE / 2.0)),
-                            Some(bevy::sprite::Anchor::Custom(Vec2::new(0., 6.))),
-                        ),
-                    ),
-                );
-                spawn_as_child(
-                    cb,
-                    (
-                        Piece::new("ceiling2"),
-                        PieceMeta::new(tr(Transform::default()), None, None),
-                    ),
-                );
+                tiles.insert(tile.grid_pos(), tile);
             }
         }
-        _ => {}
+
+        let building_tiles: Vec<IVec2> = tiles
+            .values()
+            .filter(|tile| tile.tp == TileType::Building)
+            .map(Tile::grid_pos)
+            .collect();
+        self.buildings = BuildingBlock::group(&self.sets.palette, &building_tiles)
+            .into_iter()
+            .flat_map(|block| {
+                let building = block.building;
+                block
+                    .tiles
+                    .into_iter()
+                    .map(move |tile| (tile, building.clone()))
+            })
+            .collect();
+        self.tiles = tiles;
+    }
+
+    /// Rows of tiles from the bottom up
+    pub fn raw_map(&self) -> &[Vec<TileType>] {
+        &self.raw
+    }
+
+    pub fn tile_type(&self, pos: IVec2) -> Option<TileType> {
+        self.tiles.get(&pos).map(|tile| tile.tp)
+    }
+
+    pub fn tile(&self, pos: IVec2) -> Option<&Tile> {
+        self.tiles.get(&pos)
+    }
+
+    /// Changes the tile at `pos` and respawns the loaded chunks that look different because of it.
+    /// Returns `false` if `pos` is outside the map or already of type `tp`
+    pub fn set_tile(&mut self, commands: &mut Commands, pos: IVec2, tp: TileType) -> bool {
+        let Some(cell) = usize::try_from(pos.y)
+            .ok()
+            .zip(usize::try_from(pos.x).ok())
+            .and_then(|(i, j)| self.raw.get_mut(i)?.get_mut(j))
+        else {
+            return false;
+        };
+        if *cell == tp {
+            return false;
+        }
+        *cell = tp;
+
+        let old_buildings = std::mem::take(&mut self.buildings);
+        self.rebuild();
+        // Neighbours are autotiled again, and blocks may have been joined or split
+        let mut changed: Vec<IVec2> = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| pos + IVec2::new(x, y)))
+            .collect();
+        changed.extend(
+            self.buildings
+                .keys()
+                .chain(old_buildings.keys())
+                .filter(|tile| self.buildings.get(tile) != old_buildings.get(tile)),
+        );
+        let mut chunks: Vec<IVec2> = changed.iter().map(|tile| self.chunk_of(*tile)).collect();
+        chunks.sort_by_key(|c| (c.y, c.x));
+        chunks.dedup();
+        for chunk in chunks {
+            if self.loaded.contains_key(&chunk) {
+                self.despawn_chunk(commands, chunk);
+                self.spawn_chunk(commands, chunk);
+            }
+        }
+        true
+    }
+
+    /// Chunk containing the tile at `tile`
+    pub fn chunk_of(&self, tile: IVec2) -> IVec2 {
+        tile.div_euclid(IVec2::splat(self.chunk_size))
+    }
+
+    /// Every chunk with tiles in it
+    pub fn chunks(&self) -> Vec<IVec2> {
+        let mut chunks: Vec<IVec2> = self.tiles.keys().map(|t| self.chunk_of(*t)).collect();
+        chunks.sort_by_key(|c| (c.y, c.x));
+        chunks.dedup();
+        chunks
+    }
+
+    pub fn loaded_count(&self) -> usize {
+        self.loaded.len()
+    }
+
+    /// Spawns the tiles of `chunk` with their pieces, and one collider for each part of a
+    /// building block inside the chunk
+    pub fn spawn_chunk(&mut self, commands: &mut Commands, chunk: IVec2) {
+        if self.loaded.contains_key(&chunk) {
+            return;
+        }
+        let min = chunk * self.chunk_size;
+        let positions: Vec<IVec2> = (min.y..min.y + self.chunk_size)
+            .flat_map(|y| (min.x..min.x + self.chunk_size).map(move |x| IVec2::new(x, y)))
+            .filter(|pos| self.tiles.contains_key(pos))
+            .collect();
+        if positions.is_empty() {
+            return;
+        }
+        let building_tiles: Vec<IVec2> = positions
+            .iter()
+            .copied()
+            .filter(|pos| self.buildings.contains_key(pos))
+            .collect();
+
+        let entity = commands
+            .spawn((Chunk { pos: chunk }, SpatialBundle::default()))
+            .with_children(|cb| {
+                for pos in &positions {
+                    let tile = &self.tiles[pos];
+                    let building = self.buildings.get(pos);
+                    cb.spawn(TileBundle::new(tile.clone()))
+                        .with_children(|cb| spawn_pieces(cb, tile, &self.sets, building));
+                }
+                for mut block in BuildingBlock::group(&self.sets.palette, &building_tiles) {
+                    block.building = self.buildings[&block.tiles[0]].clone();
+                    cb.spawn((block.collider(), TransformBundle::default(), block));
+                }
+            })
+            .id();
+        self.loaded.insert(chunk, entity);
+    }
+
+    pub fn despawn_chunk(&mut self, commands: &mut Commands, chunk: IVec2) {
+        if let Some(entity) = self.loaded.remove(&chunk) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    fn despawn_all(&mut self, commands: &mut Commands) {
+        let chunks: Vec<IVec2> = self.loaded.keys().copied().collect();
+        for chunk in chunks {
+            self.despawn_chunk(commands, chunk);
+        }
     }
 }
 
-pub fn setup_tilemap(mut commands: Commands) {
-    let raw_map = load_map();
-    let mut tiles = Vec::new();
-    for (i, row) in raw_map.iter().enumerate() {
-        for (j, &tp) in row.iter().enumerate() {
-            tiles.push(Tile {
-                tp,
-                pos: Vec2::new(j as f32, i as f32),
-                neighbors: Neighbors {
-                    north: i + 1 < raw_map.len() && tp == raw_map[i + 1][j],
-                    east: j + 1 < raw_map[i].len() && tp == raw_map[i][j + 1],
-                    south: i > 0 && tp == raw_map[i - 1][j],
-                    west: j > 0 && tp == raw_map[i][j - 1],
-                },
-            });
+pub fn setup_tilemap(mut commands: Commands, map: Res<CityMap>, constants: Res<Constants>) {
+    commands.insert_resource(ChunkedMap::new(&map.tiles, constants.map.chunk_size));
+    for piece in &map.placements.pieces {
+        piece.spawn(&mut commands);
+    }
+}
+
+/// Keeps the chunks around an entity loaded as if a camera was there, for cars that need the
+/// building colliders out of view
+#[derive(Component)]
+pub struct StreamChunks;
+
+/// Spawns the chunks around the cameras and [`StreamChunks`] entities, and despawns those left
+/// behind with a chunk of slack so driving back and forth over a chunk border doesn't respawn
+/// them every time
+pub fn stream_chunks(
+    mut commands: Commands,
+    constants: Res<Constants>,
+    mut map: ResMut<ChunkedMap>,
+    camera_q: Query<&Transform, (With<Camera2d>, Without<HudCamera>)>,
+    streamed_q: Query<&Transform, With<StreamChunks>>,
+) {
+    if map.chunk_size != constants.map.chunk_size.max(1) {
+        map.despawn_all(&mut commands);
+        map.chunk_size = constants.map.chunk_size.max(1);
+    }
+    let radius = constants.map.stream_radius.max(0);
+    let centers: Vec<IVec2> = camera_q
+        .iter()
+        .chain(streamed_q.iter())
+        .map(|camera| map.chunk_of((camera.translation.xy() / TILE_SIZE).round().as_ivec2()))
+        .collect();
+    let distance = |chunk: IVec2| {
+        centers
+            .iter()
+            .map(|center| (chunk - *center).abs().max_element())
+            .min()
+            .unwrap_or(i32::MAX)
+    };
+
+    let far: Vec<IVec2> = map
+        .loaded
+        .keys()
+        .copied()
+        .filter(|chunk| distance(*chunk) > radius + 1)
+        .collect();
+    for chunk in far {
+        map.despawn_chunk(&mut commands, chunk);
+    }
+    for center in &centers {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let chunk = *center + IVec2::new(x, y);
+                map.spawn_chunk(&mut commands, chunk);
+            }
         }
     }
-    for tile in &tiles {
-        let mut entity = commands.spawn(TileBundle::new(tile.clone()));
-        entity.with_children(|cb| {
-            spawn_pieces(cb, tile);
-        });
+}
+
+/// Spawns every tile of `raw_map` with its pieces at once, rows bottom-up
+pub fn spawn_tiles(commands: &mut Commands, raw_map: &[Vec<TileType>]) {
+    let mut map = ChunkedMap::new(raw_map, MapConstants::default().chunk_size);
+    for chunk in map.chunks() {
+        map.spawn_chunk(commands, chunk);
     }
 }
 
+/// Path of the map relative to the crate, for the editor to export to
+pub const MAP_CSV_PATH: &str = "assets/tilemap/simplified/Level_0/Map.csv";
+
 const MAP_CSV_CONTENT: &str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/assets/tilemap/simplified/Level_0/Map.csv"
 ));
 
-fn load_map() -> Vec<Vec<TileType>> {
+/// Tiles of the hand made map, rows from the bottom up
+pub fn load_map() -> Vec<Vec<TileType>> {
     MAP_CSV_CONTENT
         .lines()
         .rev()
         .map(|l| {
             l.split(',')
-                .filter_map(|val| val.parse::<i32>().ok().map(From::from))
+                .filter_map(|val| val.parse::<i32>().ok())
+                .map(|value| {
+                    TileType::try_from(value).unwrap_or_else(|e| {
+                        error!("{e} in Map.csv");
+                        TileType::Empty
+                    })
+                })
                 .collect::<Vec<TileType>>()
         })
         .collect()
 }
+
+/// The map in the format of `Map.csv`, top row first. Like LDtk's export, every row but the
+/// last ends with a comma
+pub fn to_csv(raw_map: &[Vec<TileType>]) -> String {
+    let rows: Vec<String> = raw_map
+        .iter()
+        .rev()
+        .map(|row| {
+            let values: Vec<String> = row.iter().map(|tp| i32::from(*tp).to_string()).collect();
+            values.join(",")
+        })
+        .collect();
+    rows.join(",\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_numbers_convert() {
+        assert_eq!(TileType::try_from(3), Ok(TileType::Building));
+        assert_eq!(TileType::try_from(9), Ok(TileType::OneWay(Direction::East)));
+        assert_eq!(TileType::try_from(42), Err(UnknownTile(42)));
+        for value in 0..12 {
+            assert_eq!(TileType::try_from(value).map(i32::from), Ok(value));
+        }
+        assert!(Direction::East.as_vec2().distance(Vec2::X) < 1e-5);
+        assert!(TileType::Bridge.connects(&TileType::Road));
+    }
+
+    #[test]
+    fn map_splits_into_chunks() {
+        let raw_map = vec![vec![TileType::Grass; 10]; 5];
+        let map = ChunkedMap::new(&raw_map, 4);
+        assert_eq!(map.chunk_of(IVec2::new(9, 4)), IVec2::new(2, 1));
+        assert_eq!(map.chunk_of(IVec2::new(-1, 0)), IVec2::new(-1, 0));
+        assert_eq!(map.chunks().len(), 3 * 2);
+    }
+
+    #[test]
+    fn shipped_map_round_trips_through_csv() {
+        assert_eq!(to_csv(&load_map()), MAP_CSV_CONTENT);
+    }
+}
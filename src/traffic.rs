@@ -0,0 +1,374 @@
+//! Lanes on road tiles and the traffic rules broken by driving against them, with right-hand
+//! traffic. Violations cost reputation and fines, and a bad enough reputation alerts the police
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    car::{Car, Player},
+    constants::{Constants, TILE_SIZE},
+    plugins::GameSet,
+    tilemap::{ChunkedMap, Direction, Tile, TileType},
+    zones::Fines,
+};
+
+/// Offset of the lanes of a two-way road from its center line, in tiles. The pavement is the
+/// middle third of a road tile
+const LANE_OFFSET: f32 = 1. / 12.;
+
+/// Reputation of a new driver, out of [`Reputation::MAX`]
+const START_REPUTATION: i32 = 50;
+/// Reputation below which the police start chasing the driver
+const WANTED_REPUTATION: i32 = 20;
+
+pub struct TrafficPlugin;
+
+impl Plugin for TrafficPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Reputation>()
+            .add_event::<TrafficViolation>()
+            .add_event::<PoliceAlerted>()
+            .add_systems(
+                Update,
+                (detect_violations, apply_penalties)
+                    .chain()
+                    .in_set(GameSet::Gameplay),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// Against a one-way road, or on the left side of a two-way road
+    WrongWay,
+    /// Into a junction without slowing down
+    RanCrossing,
+    /// On the sidewalk pieces at the edges of a road
+    OnSidewalk,
+}
+
+impl Violation {
+    /// Reputation lost for the violation
+    pub fn penalty(&self) -> i32 {
+        match self {
+            Violation::WrongWay => 10,
+            Violation::RanCrossing => 5,
+            Violation::OnSidewalk => 15,
+        }
+    }
+
+    pub fn fine(&self) -> u32 {
+        match self {
+            Violation::WrongWay => 40,
+            Violation::RanCrossing => 20,
+            Violation::OnSidewalk => 60,
+        }
+    }
+}
+
+/// The car `car` broke a traffic rule
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TrafficViolation {
+    pub car: Entity,
+    pub violation: Violation,
+}
+
+/// `car` broke a rule with a reputation bad enough for the police to come after them, or to
+/// send more cars if they already are
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PoliceAlerted {
+    pub car: Entity,
+}
+
+/// How well the player is known to drive, lowered by violations and raised by finished fares
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Reputation {
+    pub points: i32,
+}
+
+impl Default for Reputation {
+    fn default() -> Self {
+        Self {
+            points: START_REPUTATION,
+        }
+    }
+}
+
+impl Reputation {
+    pub const MAX: i32 = 100;
+
+    pub fn change(&mut self, amount: i32) {
+        self.points = (self.points + amount).clamp(0, Self::MAX);
+    }
+
+    /// Just good enough not to be wanted anymore, after being arrested
+    pub fn pardon(&mut self) {
+        self.points = self.points.max(WANTED_REPUTATION);
+    }
+
+    pub fn wanted(&self) -> bool {
+        self.points < WANTED_REPUTATION
+    }
+
+    /// Passengers pay between half and one and a half times the fare, the full fare at the
+    /// starting reputation
+    pub fn fare_multiplier(&self) -> f32 {
+        0.5 + self.points as f32 / Self::MAX as f32
+    }
+}
+
+/// A lane of a road tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lane {
+    pub direction: Direction,
+    /// Offset of the lane from the center of the road to the right of `direction`, in tiles
+    pub offset: f32,
+}
+
+/// The shape of a road tile that its lanes and sidewalks follow
+#[derive(Debug, Clone, Copy)]
+pub struct RoadLayout {
+    pub tp: TileType,
+    /// Whether the north, east, south and west neighbours are roads
+    pub sides: [bool; 4],
+    /// Whether the north-east, south-east, south-west and north-west neighbours are roads
+    pub corners: [bool; 4],
+}
+
+impl RoadLayout {
+    pub fn of(tile: &Tile) -> Self {
+        Self {
+            tp: tile.tile_type(),
+            sides: tile.sides(),
+            corners: tile.corners(),
+        }
+    }
+
+    pub fn is_junction(&self) -> bool {
+        self.sides.iter().filter(|side| **side).count() >= 3
+    }
+
+    /// Lanes along the road, one on a one-way road and one in each direction otherwise
+    pub fn lanes(&self) -> Vec<Lane> {
+        let [north, east, south, west] = self.sides;
+        let mut lanes = Vec::new();
+        for (connected, forward, backward) in [
+            (north || south, Direction::North, Direction::South),
+            (east || west, Direction::East, Direction::West),
+        ] {
+            if !connected {
+                continue;
+            }
+            match self.tp.one_way() {
+                Some(direction) if direction.is_vertical() == forward.is_vertical() => {
+                    lanes.push(Lane {
+                        direction,
+                        offset: 0.,
+                    });
+                }
+                _ => lanes.extend([forward, backward].map(|direction| Lane {
+                    direction,
+                    offset: LANE_OFFSET,
+                })),
+            }
+        }
+        lanes
+    }
+
+    /// Whether `local`, in tiles from the tile center, is on one of the sidewalks the road
+    /// pieces put on the thirds of the tile not leading to another road
+    pub fn on_sidewalk(&self, local: Vec2) -> bool {
+        if !matches!(self.tp, TileType::Road | TileType::OneWay(_)) {
+            return false;
+        }
+        let cell = (local * 3.)
+            .round()
+            .clamp(Vec2::NEG_ONE, Vec2::ONE)
+            .as_ivec2();
+        let [north, east, south, west] = self.sides;
+        let vertical = if cell.y > 0 { north } else { south };
+        let horizontal = if cell.x > 0 { east } else { west };
+        let corner = match (cell.x > 0, cell.y > 0) {
+            (true, true) => self.corners[0],
+            (true, false) => self.corners[1],
+            (false, false) => self.corners[2],
+            (false, true) => self.corners[3],
+        };
+        let pavement = match (cell.x, cell.y) {
+            (0, 0) => true,
+            (0, _) => vertical,
+            (_, 0) => horizontal,
+            // Corners only have no sidewalk where the roads around them meet diagonally too
+            _ => vertical && horizontal && corner,
+        };
+        !pavement
+    }
+
+    /// Which rule a car at `local` moving at `velocity` is breaking, apart from running through
+    /// junctions. Cars slower than `min_speed`, e.g. pulled over at the curb, break none
+    pub fn check(
+        &self,
+        local: Vec2,
+        velocity: Vec2,
+        min_speed: f32,
+        margin: f32,
+    ) -> Option<Violation> {
+        if velocity.length() < min_speed {
+            return None;
+        }
+        if self.on_sidewalk(local) {
+            return Some(Violation::OnSidewalk);
+        }
+        // Junctions can be crossed in any direction
+        if self.is_junction() {
+            return None;
+        }
+        let heading = Direction::nearest(velocity);
+        let lanes: Vec<Lane> = self
+            .lanes()
+            .into_iter()
+            .filter(|lane| lane.direction.is_vertical() == heading.is_vertical())
+            .collect();
+        if lanes.is_empty() {
+            // Crossing the road rather than driving along it
+            return None;
+        }
+        let Some(lane) = lanes.iter().find(|lane| lane.direction == heading) else {
+            return Some(Violation::WrongWay);
+        };
+        let forward = heading.as_vec2();
+        let right = Vec2::new(forward.y, -forward.x);
+        let lateral = local.dot(right);
+        (lane.offset > 0. && lateral < -margin).then_some(Violation::WrongWay)
+    }
+}
+
+/// Tile and broken rule of each car on the previous frame, so each violation is reported once
+#[derive(Default)]
+pub struct ViolationTracker {
+    cars: HashMap<Entity, (IVec2, Option<Violation>)>,
+}
+
+pub fn detect_violations(
+    map: Option<Res<ChunkedMap>>,
+    constants: Res<Constants>,
+    car_q: Query<(Entity, &Transform, &Velocity), (With<Car>, With<Player>)>,
+    mut tracker: Local<ViolationTracker>,
+    mut violations: EventWriter<TrafficViolation>,
+) {
+    let Some(map) = map else {
+        return;
+    };
+    let traffic = constants.traffic;
+    for (car, transform, velocity) in car_q.iter() {
+        let pos = transform.translation.xy() / TILE_SIZE;
+        let tile_pos = pos.round().as_ivec2();
+        let Some(tile) = map.tile(tile_pos).filter(|tile| tile.tile_type().is_road()) else {
+            tracker.cars.remove(&car);
+            continue;
+        };
+        let layout = RoadLayout::of(tile);
+        let velocity = velocity.linvel;
+
+        let previous = tracker.cars.get(&car).copied();
+        let entered = previous.map_or(true, |(tile, _)| tile != tile_pos);
+        if entered && layout.is_junction() && velocity.length() > traffic.crossing_speed {
+            violations.send(TrafficViolation {
+                car,
+                violation: Violation::RanCrossing,
+            });
+        }
+
+        let violation = layout.check(
+            pos - tile_pos.as_vec2(),
+            velocity,
+            traffic.min_speed,
+            traffic.lane_margin,
+        );
+        // Moving to the next tile of the same road while breaking the same rule is still the
+        // same violation
+        let ongoing = previous.and_then(|(_, violation)| violation);
+        if let Some(violation) = violation.filter(|v| ongoing != Some(*v)) {
+            violations.send(TrafficViolation { car, violation });
+        }
+        tracker.cars.insert(car, (tile_pos, violation));
+    }
+}
+
+pub fn apply_penalties(
+    mut violations: EventReader<TrafficViolation>,
+    mut reputation: ResMut<Reputation>,
+    mut alerts: EventWriter<PoliceAlerted>,
+    mut fines: Option<ResMut<Fines>>,
+) {
+    for TrafficViolation { car, violation } in violations.read() {
+        reputation.change(-violation.penalty());
+        if let Some(fines) = fines.as_mut() {
+            fines.total += violation.fine();
+        }
+        info!("{violation:?}, reputation now {}", reputation.points);
+        if reputation.wanted() {
+            alerts.send(PoliceAlerted { car: *car });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straight_north() -> RoadLayout {
+        RoadLayout {
+            tp: TileType::Road,
+            sides: [true, false, true, false],
+            corners: [false; 4],
+        }
+    }
+
+    #[test]
+    fn two_way_roads_are_driven_on_the_right() {
+        let road = straight_north();
+        let north = Vec2::new(0., 500.);
+        let check = |x: f32, velocity| road.check(Vec2::new(x, 0.), velocity, 100., 0.04);
+        assert_eq!(check(0.1, north), None);
+        assert_eq!(check(-0.1, north), Some(Violation::WrongWay));
+        assert_eq!(check(-0.1, -north), None);
+        // Standing still or on the sidewalk
+        assert_eq!(check(-0.1, Vec2::ZERO), None);
+        assert_eq!(check(0.4, north), Some(Violation::OnSidewalk));
+    }
+
+    #[test]
+    fn one_way_roads_have_one_lane() {
+        let road = RoadLayout {
+            tp: TileType::OneWay(Direction::East),
+            sides: [false, true, false, true],
+            corners: [false; 4],
+        };
+        assert_eq!(road.lanes().len(), 1);
+        let check = |velocity| road.check(Vec2::new(0., -0.1), velocity, 100., 0.04);
+        assert_eq!(check(Vec2::new(500., 0.)), None);
+        assert_eq!(check(Vec2::new(-500., 0.)), Some(Violation::WrongWay));
+    }
+
+    #[test]
+    fn crossings_only_have_sidewalks_on_the_corners() {
+        let crossing = RoadLayout {
+            tp: TileType::Road,
+            sides: [true; 4],
+            corners: [true, false, false, false],
+        };
+        assert!(!crossing.on_sidewalk(Vec2::new(0.4, 0.)));
+        assert!(!crossing.on_sidewalk(Vec2::new(0.4, 0.4)));
+        assert!(crossing.on_sidewalk(Vec2::new(-0.4, -0.4)));
+    }
+
+    #[test]
+    fn reputation_sets_the_fare() {
+        let mut reputation = Reputation::default();
+        assert_eq!(reputation.fare_multiplier(), 1.);
+        reputation.change(-40);
+        assert!(reputation.wanted());
+        assert!(reputation.fare_multiplier() < 1.);
+    }
+}
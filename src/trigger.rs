@@ -1,29 +1,125 @@
 use bevy::prelude::*;
-use bevy_inspector_egui::InspectorOptions;
 use bevy_rapier2d::{prelude::*, rapier::geometry::CollisionEventFlags};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 use crate::{
+    car::{Car, Player},
     constants::Constants,
-    dialogues::{DialogueHandle, DialogueList, DialogueState},
-    missions::MissionState,
+    plugins::GameSet,
 };
 
+/// Turns Rapier sensor collisions with the player car into [`TriggerEntered`] and
+/// [`TriggerExited`] events
+pub struct TriggerPlugin;
+
+impl Plugin for TriggerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TriggerEntered>()
+            .add_event::<TriggerExited>()
+            .add_systems(Startup, setup_trigger)
+            .add_systems(Update, route_trigger_collisions.in_set(GameSet::Gameplay));
+    }
+}
+
+/// The player car `by` started overlapping `trigger`
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TriggerEntered {
+    pub trigger: Entity,
+    pub by: Entity,
+}
+
+/// The player car `by` stopped overlapping `trigger`
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TriggerExited {
+    pub trigger: Entity,
+    pub by: Entity,
+}
+
 #[derive(Component)]
 pub struct Trigger {}
 
 #[derive(Component)]
 pub struct Target {}
 
-#[derive(Debug, Clone, Copy, Component, InspectorOptions)]
+#[derive(Debug, Clone, PartialEq, Component, Deserialize, Serialize)]
 pub enum TriggerType {
     StartMission,
     StopMission,
+    /// Fines the player for driving through faster than `limit`
+    SpeedTrap {
+        limit: f32,
+        fine: u32,
+    },
+    /// Fines the player for entering at all
+    Restricted {
+        fine: u32,
+    },
+    /// Plays the named dialogue if nothing else is being said
+    Dialogue {
+        name: String,
+    },
+    Checkpoint {
+        index: usize,
+    },
+    /// Hides the player while inside
+    Garage,
+}
+
+#[derive(Debug, Clone, Component, Deserialize, Serialize)]
+pub enum TriggerShape {
+    Rectangle {
+        half_size: Vec2,
+    },
+    Circle {
+        radius: f32,
+    },
+    /// Points relative to the trigger position, in order around the outline, at least 3
+    Polygon {
+        #[serde(deserialize_with = "polygon_points")]
+        points: Vec<Vec2>,
+    },
+}
+
+fn polygon_points<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec2>, D::Error> {
+    let points = Vec::<Vec2>::deserialize(deserializer)?;
+    if points.len() < 3 {
+        return Err(D::Error::invalid_length(points.len(), &"at least 3 points"));
+    }
+    Ok(points)
+}
+
+impl TriggerShape {
+    /// The sensor collider of the shape, or `None` for a polygon without any area that Rapier
+    /// can't decompose
+    pub fn collider(&self) -> Option<Collider> {
+        match self {
+            TriggerShape::Rectangle { half_size } => {
+                Some(Collider::cuboid(half_size.x, half_size.y))
+            }
+            TriggerShape::Circle { radius } => Some(Collider::ball(*radius)),
+            TriggerShape::Polygon { points } => {
+                // Shoelace formula, twice the area
+                let area = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(a, b)| a.perp_dot(*b))
+                    .sum::<f32>();
+                if points.len() < 3 || !area.is_finite() || area.abs() < f32::EPSILON {
+                    return None;
+                }
+                let len = points.len() as u32;
+                let indices: Vec<[u32; 2]> = (0..len).map(|i| [i, (i + 1) % len]).collect();
+                Some(Collider::convex_decomposition(points, &indices))
+            }
+        }
+    }
 }
 
 #[derive(Bundle)]
 pub struct TriggerBundle {
     trigger: Trigger,
     trigger_type: TriggerType,
+    shape: TriggerShape,
     sprite: SpriteBundle,
     collider: Collider,
     sensor: Sensor,
@@ -32,9 +128,12 @@ pub struct TriggerBundle {
 
 impl TriggerBundle {
     pub fn new(trigger_type: TriggerType, constants: &Res<Constants>) -> Self {
+        let size = constants.trigger.size;
         Self {
             trigger: Trigger {},
             trigger_type,
+            collider: Collider::cuboid(size.x, size.y),
+            shape: TriggerShape::Rectangle { half_size: size },
             sprite: SpriteBundle {
                 transform: Transform {
                     translation: Vec3::new(-200., -200., 0.),
@@ -51,7 +150,6 @@ impl TriggerBundle {
                 },
                 ..Default::default()
             },
-            collider: Collider::cuboid(constants.trigger.size.x, constants.trigger.size.y),
             sensor: Sensor {},
             active_events: ActiveEvents::COLLISION_EVENTS,
         }
@@ -73,85 +171,38 @@ pub fn setup_trigger(commands: Commands, constants: Res<Constants>) {
     */
 }
 
-pub fn handle_trigger_collisions(
-    mut commands: Commands,
-    // mut car_q: Query<&mut CarState, (With<Car>, With<Player>)>,
+pub fn route_trigger_collisions(
     mut collision_events: EventReader<CollisionEvent>,
-    mut mission_state: ResMut<MissionState>,
-    spawn_q: Query<(Entity, &TriggerType), With<Trigger>>,
-    constants: Res<Constants>,
-    mut dialogue_state: ResMut<DialogueState>,
-    mut dialogues: ResMut<Assets<DialogueList>>,
-    dialogue: Res<DialogueHandle>,
+    trigger_q: Query<(), With<Trigger>>,
+    player_q: Query<(), (With<Car>, With<Player>)>,
+    mut entered: EventWriter<TriggerEntered>,
+    mut exited: EventWriter<TriggerExited>,
 ) {
-    // let mut car_state = car_q.get_single_mut().unwrap();
-
     for event in collision_events.read() {
-        match event {
-            CollisionEvent::Started(c1, c2, flags) => {
-                if flags != &CollisionEventFlags::SENSOR {
-                    return;
-                }
+        let (c1, c2, flags, started) = match *event {
+            CollisionEvent::Started(c1, c2, flags) => (c1, c2, flags, true),
+            CollisionEvent::Stopped(c1, c2, flags) => (c1, c2, flags, false),
+        };
+        if !flags.contains(CollisionEventFlags::SENSOR) {
+            continue;
+        }
 
-                let a = spawn_q
-                    .iter()
-                    .find(|(e, _)| commands.entity(*e).id() == commands.entity(*c1).id());
-                let b = spawn_q
-                    .iter()
-                    .find(|(e, _)| commands.entity(*e).id() == commands.entity(*c2).id());
-
-                let sensor = if a.is_some() {
-                    a
-                } else if b.is_some() {
-                    b
-                } else {
-                    None
-                };
-
-                if let Some(trigger_type) = sensor.map(|(_, t)| t) {
-                    match trigger_type {
-                        TriggerType::StartMission => {
-                            if mission_state.mission_active {
-                                return;
-                            }
-                            commands.entity(sensor.unwrap().0).despawn();
-                            let mission_idx = mission_state.next_target(&mut commands, &constants);
-
-                            dialogue_state.load_dialogue(
-                                &format!("p{}", mission_idx.unwrap()),
-                                &mut dialogues,
-                                &dialogue,
-                            );
-                        }
-                        TriggerType::StopMission => {
-                            if !mission_state.mission_active {
-                                return;
-                            }
-
-                            commands.entity(sensor.unwrap().0).despawn();
-                            mission_state.next_target(&mut commands, &constants);
-
-                            dialogue_state.load_dialogue("p-end", &mut dialogues, &dialogue);
-                        }
-                    }
-                }
-            }
-            CollisionEvent::Stopped(_, sensor, flags) => {
-                if flags != &CollisionEventFlags::SENSOR {
-                    return;
-                }
+        let (trigger, by) = if trigger_q.contains(c1) {
+            (c1, c2)
+        } else if trigger_q.contains(c2) {
+            (c2, c1)
+        } else {
+            continue;
+        };
+        // Only the car body counts, so its tires don't cause duplicate events
+        if !player_q.contains(by) {
+            continue;
+        }
 
-                if let Some(trigger_type) = spawn_q
-                    .iter()
-                    .find(|(e, _)| commands.entity(*e).id() == commands.entity(*sensor).id())
-                    .map(|(_e, t)| t)
-                {
-                    match trigger_type {
-                        TriggerType::StartMission => {}
-                        TriggerType::StopMission => {}
-                    }
-                }
-            }
+        if started {
+            entered.send(TriggerEntered { trigger, by });
+        } else {
+            exited.send(TriggerExited { trigger, by });
         }
     }
 }
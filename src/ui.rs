@@ -1,12 +1,38 @@
 use crate::constants::*;
-use crate::{dialogues::DialogueBundle, tilemap::Tile};
+use crate::{plugins::GameSet, tilemap::Tile, zones::draw_zones};
 use bevy::{
     diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    input::common_conditions::input_toggle_active,
     prelude::*,
     window::PrimaryWindow,
 };
 use bevy_egui::{egui, EguiContext};
-use bevy_inspector_egui::bevy_inspector;
+use bevy_inspector_egui::{
+    bevy_inspector, quick::ResourceInspectorPlugin, DefaultInspectorConfigPlugin,
+};
+
+/// FPS counter, the F1/F2 world and entity inspectors and trigger zone outlines with F1
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            FrameTimeDiagnosticsPlugin,
+            DefaultInspectorConfigPlugin,
+            ResourceInspectorPlugin::<Constants>::default(),
+        ))
+        .add_systems(
+            Update,
+            (
+                world_inspector.run_if(input_toggle_active(false, KeyCode::F1)),
+                draw_zones.run_if(input_toggle_active(false, KeyCode::F1)),
+                entity_inspector.run_if(input_toggle_active(false, KeyCode::F2)),
+                show_fps,
+            )
+                .in_set(GameSet::Ui),
+        );
+    }
+}
 
 // Inspectors for debugging
 pub fn world_inspector(world: &mut World) {
@@ -39,11 +65,6 @@ pub fn entity_inspector(world: &mut World) {
     });
 }
 
-pub fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>, constants: Res<Constants>) {
-    let font = asset_server.load("fonts/ComicMono.ttf");
-    commands.spawn(DialogueBundle::new(constants, font));
-}
-
 pub fn show_fps(
     mut egui_context: Query<&mut EguiContext, With<PrimaryWindow>>,
     diagnostics: Res<DiagnosticsStore>,
@@ -1,6 +1,7 @@
 use bevy::{
     ecs::{archetype::Archetypes, component::ComponentId},
     prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
 };
 
 #[allow(unused)]
@@ -15,3 +16,62 @@ pub fn get_components_for_entity<'a>(
     }
     None
 }
+
+/// White square texture with the alpha given by `alpha` for each point in `[-1, 1]²`, y up
+pub fn alpha_image(size: u32, alpha: impl Fn(Vec2) -> f32) -> Image {
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let p = Vec2::new(x as f32 + 0.5, (size - y) as f32 - 0.5) / size as f32 * 2. - 1.;
+            let a = (alpha(p).clamp(0., 1.) * 255.) as u8;
+            data.extend_from_slice(&[255, 255, 255, a]);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Xorshift, enough to pick things at random the same way every time for the same seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Below `n`, or 0 if `n` is 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    /// Between `min` and `max`, inclusive
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next_u64() % (max - min + 1).max(1) as u64) as i32
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        (self.next_u64() % 1000) as f32 / 1000. < probability
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,342 @@
+//! Rain, snow and fog changing visuals, tire grip and passenger small talk
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    camera::{view_layer, PlayerCamera},
+    car::TireGrip,
+    constants::{Constants, WeatherConstants},
+    daynight::{LIGHT_Z, NIGHT_Z},
+    missions::{handle_mission_triggers, MissionEvent, MISSION_WEATHER},
+    plugins::GameSet,
+    utility::{alpha_image, Rng},
+};
+
+/// Fog is drawn over the night tint but under the lights, so lights shine through it
+const FOG_Z: f32 = NIGHT_Z + 0.5;
+const PARTICLE_Z: f32 = LIGHT_Z + 1.;
+const PARTICLE_COUNT: usize = 400;
+const FOG_TEXTURE_SIZE: u32 = 256;
+/// The fog sprite is this many times larger than the visible area, so it covers the whole view
+const FOG_SPREAD: f32 = 4.;
+
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WeatherState>()
+            .add_systems(Startup, setup_weather)
+            .add_systems(
+                Update,
+                (choose_weather, update_weather)
+                    .chain()
+                    .after(handle_mission_triggers)
+                    .in_set(GameSet::Gameplay),
+            )
+            .add_systems(
+                Update,
+                (add_view_weather, (move_particles, update_fog))
+                    .chain()
+                    .in_set(GameSet::Ui),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Weather {
+    #[default]
+    Clear,
+    Rain,
+    Snow,
+    Fog,
+}
+
+impl Weather {
+    const ALL: [Weather; 4] = [Weather::Clear, Weather::Rain, Weather::Snow, Weather::Fog];
+
+    /// Suffix of the weather specific variants of dialogues
+    pub fn key(&self) -> &'static str {
+        match self {
+            Weather::Clear => "clear",
+            Weather::Rain => "rain",
+            Weather::Snow => "snow",
+            Weather::Fog => "fog",
+        }
+    }
+
+    fn grip(&self, constants: &WeatherConstants) -> f32 {
+        match self {
+            Weather::Clear | Weather::Fog => 1.,
+            Weather::Rain => constants.rain_grip,
+            Weather::Snow => constants.snow_grip,
+        }
+    }
+
+    /// Distance from the camera at which everything is hidden in fog
+    fn visibility(&self, constants: &WeatherConstants) -> Option<f32> {
+        match self {
+            Weather::Clear => None,
+            Weather::Rain => Some(constants.rain_visibility),
+            Weather::Snow => Some(constants.snow_visibility),
+            Weather::Fog => Some(constants.fog_visibility),
+        }
+    }
+
+    /// Falling velocity, size and color of the particles
+    fn particles(&self) -> Option<(Vec2, Vec2, Color)> {
+        match self {
+            Weather::Rain => Some((
+                Vec2::new(-150., -2400.),
+                Vec2::new(3., 70.),
+                Color::rgba(0.7, 0.8, 1., 0.5),
+            )),
+            Weather::Snow => Some((
+                Vec2::new(40., -250.),
+                Vec2::new(12., 12.),
+                Color::rgba(1., 1., 1., 0.8),
+            )),
+            Weather::Clear | Weather::Fog => None,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct WeatherState {
+    pub current: Weather,
+    /// Weather to change to, the current one fades out first
+    pub target: Weather,
+    /// How strongly the current weather is showing, between 0 and 1
+    pub intensity: f32,
+    /// Set while a mission has chosen the weather, so it doesn't change randomly
+    forced: bool,
+    timer: Timer,
+    /// Seeded the same on every run, so random weather is too
+    rng: Rng,
+}
+
+impl Default for WeatherState {
+    fn default() -> Self {
+        Self {
+            current: Weather::Clear,
+            target: Weather::Clear,
+            intensity: 0.,
+            forced: false,
+            timer: Timer::from_seconds(
+                WeatherConstants::default().change_interval,
+                TimerMode::Repeating,
+            ),
+            rng: Rng::new(2024),
+        }
+    }
+}
+
+impl WeatherState {
+    /// Current distance at which everything disappears in fog, if any
+    pub fn visibility(&self, constants: &WeatherConstants) -> Option<f32> {
+        // Fog closes in from far away as the weather gets stronger
+        self.current
+            .visibility(constants)
+            .map(|v| v / self.intensity.max(0.05))
+    }
+}
+
+#[derive(Component)]
+pub struct WeatherParticle {
+    /// Multiplier for the falling speed so particles don't move in lockstep
+    speed: f32,
+    /// Index of the player whose view the particle falls in
+    view: usize,
+}
+
+/// Fog around the view of the player with this index
+#[derive(Component)]
+pub struct FogOverlay(pub usize);
+
+#[derive(Resource)]
+pub struct FogTexture(Handle<Image>);
+
+pub fn setup_weather(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    // Clear around the center and thickening towards the edges
+    let fog = alpha_image(FOG_TEXTURE_SIZE, |p| {
+        (p.length() * FOG_SPREAD - 0.25) / 0.75
+    });
+    commands.insert_resource(FogTexture(images.add(fog)));
+}
+
+/// Spawns fog and particles for the view of every player on its own render layer, so each half
+/// of a split screen has weather of its own
+pub fn add_view_weather(
+    mut commands: Commands,
+    fog: Res<FogTexture>,
+    camera_q: Query<&PlayerCamera>,
+    fog_q: Query<&FogOverlay>,
+) {
+    for camera in camera_q.iter() {
+        let view = camera.0;
+        if fog_q.iter().any(|fog| fog.0 == view) {
+            continue;
+        }
+        commands.spawn((
+            SpriteBundle {
+                texture: fog.0.clone(),
+                visibility: Visibility::Hidden,
+                transform: Transform::from_xyz(0., 0., FOG_Z),
+                ..Default::default()
+            },
+            FogOverlay(view),
+            view_layer(view),
+        ));
+
+        let mut rng = Rng::new(view as u64);
+        for i in 0..PARTICLE_COUNT {
+            // Spread over an area much larger than the view, wrapped into view when moved
+            let x = rng.below(20_000) as f32;
+            let y = rng.below(20_000) as f32;
+            commands.spawn((
+                SpriteBundle {
+                    visibility: Visibility::Hidden,
+                    transform: Transform::from_xyz(x, y, PARTICLE_Z),
+                    ..Default::default()
+                },
+                WeatherParticle {
+                    speed: 0.75 + 0.5 * (i % 7) as f32 / 6.,
+                    view,
+                },
+                view_layer(view),
+            ));
+        }
+    }
+}
+
+/// Switches the weather when a mission asks for it, and randomly otherwise
+pub fn choose_weather(
+    time: Res<Time>,
+    constants: Res<Constants>,
+    mut mission_events: EventReader<MissionEvent>,
+    mut state: ResMut<WeatherState>,
+) {
+    for event in mission_events.read() {
+        match *event {
+            MissionEvent::Started(mission, _) => {
+                if let Some(Some(weather)) = MISSION_WEATHER.get(mission.wrapping_sub(1)) {
+                    state.target = *weather;
+                    state.forced = true;
+                }
+            }
+            MissionEvent::Finished(..) => state.forced = false,
+        }
+    }
+
+    let interval = constants.weather.change_interval.max(1.);
+    if (state.timer.duration().as_secs_f32() - interval).abs() > 0.01 {
+        state.timer = Timer::from_seconds(interval, TimerMode::Repeating);
+    }
+    if state.timer.tick(time.delta()).just_finished() && !state.forced {
+        let i = state.rng.below(Weather::ALL.len());
+        state.target = Weather::ALL[i];
+    }
+}
+
+pub fn update_weather(
+    time: Res<Time>,
+    constants: Res<Constants>,
+    mut state: ResMut<WeatherState>,
+    mut grip: ResMut<TireGrip>,
+) {
+    // Fade out the current weather before fading in the next one
+    let step = time.delta_seconds() / constants.weather.transition_time.max(0.01);
+    if state.current == state.target {
+        state.intensity = (state.intensity + step).min(1.);
+    } else {
+        state.intensity -= step;
+        if state.intensity <= 0. {
+            state.intensity = 0.;
+            state.current = state.target;
+        }
+    }
+
+    grip.0 = 1. + (state.current.grip(&constants.weather) - 1.) * state.intensity;
+}
+
+pub fn move_particles(
+    time: Res<Time>,
+    state: Res<WeatherState>,
+    camera_q: Query<(&PlayerCamera, &Transform, &OrthographicProjection), With<Camera2d>>,
+    mut particle_q: Query<
+        (
+            &WeatherParticle,
+            &mut Transform,
+            &mut Sprite,
+            &mut Visibility,
+        ),
+        Without<Camera2d>,
+    >,
+) {
+    let Some((velocity, size, color)) = state.current.particles() else {
+        for (_, _, _, mut visibility) in particle_q.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    };
+
+    let color = color.with_a(color.a() * state.intensity);
+    for (particle, mut transform, mut sprite, mut visibility) in particle_q.iter_mut() {
+        let Some((_, camera, projection)) = camera_q.iter().find(|(c, ..)| c.0 == particle.view)
+        else {
+            continue;
+        };
+        let view_min = camera.translation.xy() + projection.area.min;
+        let view_size = projection.area.size();
+        *visibility = Visibility::Visible;
+        sprite.color = color;
+        sprite.custom_size = Some(size);
+
+        // Particles falling out of view wrap around to the other side
+        let pos = transform.translation.xy() + particle.speed * velocity * time.delta_seconds();
+        let offset = pos - view_min;
+        let pos = view_min
+            + Vec2::new(
+                offset.x.rem_euclid(view_size.x),
+                offset.y.rem_euclid(view_size.y),
+            );
+        transform.translation = pos.extend(PARTICLE_Z);
+        transform.rotation = Quat::from_rotation_z(velocity.x.atan2(-velocity.y));
+    }
+}
+
+pub fn update_fog(
+    constants: Res<Constants>,
+    state: Res<WeatherState>,
+    camera_q: Query<(&PlayerCamera, &Transform), With<Camera2d>>,
+    mut fog_q: Query<
+        (&FogOverlay, &mut Transform, &mut Sprite, &mut Visibility),
+        Without<Camera2d>,
+    >,
+) {
+    for (fog, mut transform, mut sprite, mut visibility) in fog_q.iter_mut() {
+        let Some((_, camera)) = camera_q.iter().find(|(c, _)| c.0 == fog.0) else {
+            continue;
+        };
+        let Some(distance) = state.visibility(&constants.weather) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Visible;
+        sprite.custom_size = Some(Vec2::splat(2. * FOG_SPREAD * distance));
+        sprite.color = constants.weather.fog_color;
+        transform.translation = camera.translation.xy().extend(FOG_Z);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_weather_changes() {
+        let mut state = WeatherState::default();
+        let picks: Vec<usize> = (0..16).map(|_| state.rng.below(4)).collect();
+        assert!(picks.iter().any(|p| *p != picks[0]));
+    }
+}
@@ -0,0 +1,241 @@
+//! Trigger zones placed from level data, e.g. speed traps, restricted areas and garages
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plugins::GameSet,
+    tilemap::MapSource,
+    trigger::{
+        route_trigger_collisions, Trigger, TriggerEntered, TriggerExited, TriggerShape, TriggerType,
+    },
+};
+
+/// Path of the zones file relative to the crate, for the editor to export to
+pub const LEVEL_ZONES_PATH: &str = "assets/zones/level_0.zones.json";
+
+static LEVEL_ZONES: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/zones/level_0.zones.json"
+));
+
+pub struct ZonePlugin;
+
+impl Plugin for ZonePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Fines>()
+            .add_event::<ZoneEvent>()
+            .add_systems(Startup, setup_zones)
+            .add_systems(
+                Update,
+                handle_zone_triggers
+                    .after(route_trigger_collisions)
+                    .in_set(GameSet::Gameplay),
+            );
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ZoneDesc {
+    pub trigger: TriggerType,
+    pub shape: TriggerShape,
+    pub position: Vec2,
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl ZoneDesc {
+    pub fn spawn(&self, commands: &mut Commands) {
+        let transform = Transform::from_translation(self.position.extend(0.))
+            .with_rotation(Quat::from_rotation_z(self.rotation));
+        match ZoneBundle::new(self.trigger.clone(), self.shape.clone(), transform) {
+            Some(zone) => {
+                commands.spawn(zone);
+            }
+            None => error!("skipping zone at {} without any area", self.position),
+        }
+    }
+}
+
+/// Zones of the hand made level, skipping any that don't parse
+pub fn level_zones() -> Vec<ZoneDesc> {
+    let zones: Vec<serde_json::Value> = match serde_json::from_str(LEVEL_ZONES) {
+        Ok(zones) => zones,
+        Err(e) => {
+            error!("failed parsing level_0.zones.json: {e}");
+            return Vec::new();
+        }
+    };
+    zones
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, zone)| match serde_json::from_value(zone) {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                error!("skipping zone {i} of level_0.zones.json: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Fines collected from speed traps and restricted zones
+#[derive(Resource, Default, Debug)]
+pub struct Fines {
+    pub total: u32,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum ZoneEvent {
+    Fined { amount: u32 },
+    CheckpointReached(usize),
+}
+
+/// Present on a car while it is inside a garage zone
+#[derive(Component)]
+pub struct InGarage;
+
+#[derive(Bundle)]
+pub struct ZoneBundle {
+    trigger: Trigger,
+    trigger_type: TriggerType,
+    collider: Collider,
+    shape: TriggerShape,
+    transform: TransformBundle,
+    sensor: Sensor,
+    active_events: ActiveEvents,
+}
+
+impl ZoneBundle {
+    /// `None` if the shape has no collider
+    pub fn new(
+        trigger_type: TriggerType,
+        shape: TriggerShape,
+        transform: Transform,
+    ) -> Option<Self> {
+        Some(Self {
+            trigger: Trigger {},
+            trigger_type,
+            collider: shape.collider()?,
+            shape,
+            transform: TransformBundle::from_transform(transform),
+            sensor: Sensor {},
+            active_events: ActiveEvents::COLLISION_EVENTS,
+        })
+    }
+}
+
+/// Spawns the zones of the hand made level, generated cities have none of their own
+pub fn setup_zones(mut commands: Commands, source: Option<Res<MapSource>>) {
+    if let Some(MapSource::Generated { .. }) = source.as_deref() {
+        return;
+    }
+    for zone in level_zones() {
+        zone.spawn(&mut commands);
+    }
+}
+
+pub fn handle_zone_triggers(
+    mut commands: Commands,
+    mut entered: EventReader<TriggerEntered>,
+    mut exited: EventReader<TriggerExited>,
+    mut zone_events: EventWriter<ZoneEvent>,
+    mut fines: ResMut<Fines>,
+    trigger_q: Query<&TriggerType>,
+    velocity_q: Query<&Velocity>,
+) {
+    for TriggerEntered { trigger, by } in entered.read() {
+        let Ok(trigger_type) = trigger_q.get(*trigger) else {
+            continue;
+        };
+        match *trigger_type {
+            TriggerType::SpeedTrap { limit, fine } => {
+                let speed = velocity_q.get(*by).map_or(0., |v| v.linvel.length());
+                if speed > limit {
+                    fines.total += fine;
+                    zone_events.send(ZoneEvent::Fined { amount: fine });
+                }
+            }
+            TriggerType::Restricted { fine } => {
+                fines.total += fine;
+                zone_events.send(ZoneEvent::Fined { amount: fine });
+            }
+            TriggerType::Checkpoint { index } => {
+                zone_events.send(ZoneEvent::CheckpointReached(index));
+            }
+            TriggerType::Garage => {
+                commands.entity(*by).insert(InGarage);
+            }
+            _ => {}
+        }
+    }
+
+    for TriggerExited { trigger, by } in exited.read() {
+        if let Ok(TriggerType::Garage) = trigger_q.get(*trigger) {
+            commands.entity(*by).remove::<InGarage>();
+        }
+    }
+}
+
+fn zone_color(trigger_type: &TriggerType) -> Color {
+    match trigger_type {
+        TriggerType::SpeedTrap { .. } => Color::ORANGE,
+        TriggerType::Restricted { .. } => Color::RED,
+        TriggerType::Dialogue { .. } => Color::CYAN,
+        TriggerType::Checkpoint { .. } => Color::GREEN,
+        TriggerType::Garage => Color::BLUE,
+        TriggerType::StartMission | TriggerType::StopMission => Color::WHITE,
+    }
+}
+
+/// Outlines every trigger zone, for debugging
+pub fn draw_zones(
+    mut gizmos: Gizmos,
+    zone_q: Query<(&TriggerType, &TriggerShape, &GlobalTransform), With<Trigger>>,
+) {
+    for (trigger_type, shape, transform) in zone_q.iter() {
+        let color = zone_color(trigger_type);
+        let (_scale, rotation, translation) = transform.to_scale_rotation_translation();
+        let pos = translation.xy();
+        let angle = rotation.to_euler(EulerRot::XYZ).2;
+        match shape {
+            TriggerShape::Rectangle { half_size } => {
+                gizmos.rect_2d(pos, angle, 2. * *half_size, color);
+            }
+            TriggerShape::Circle { radius } => {
+                gizmos.circle_2d(pos, *radius, color);
+            }
+            TriggerShape::Polygon { points } => {
+                let rotation = Vec2::from_angle(angle);
+                gizmos.linestrip_2d(
+                    points
+                        .iter()
+                        .chain(points.first())
+                        .map(|p| pos + rotation.rotate(*p)),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_zones_parse() {
+        let zones: Vec<ZoneDesc> = serde_json::from_str(LEVEL_ZONES).unwrap();
+        assert!(!zones.is_empty());
+    }
+
+    #[test]
+    fn degenerate_polygons_are_rejected() {
+        let two_points = r#"{ "Polygon": { "points": [[0, 0], [100, 0]] } }"#;
+        assert!(serde_json::from_str::<TriggerShape>(two_points).is_err());
+        let line = r#"{ "Polygon": { "points": [[0, 0], [100, 0], [200, 0]] } }"#;
+        let line: TriggerShape = serde_json::from_str(line).unwrap();
+        assert!(line.collider().is_none());
+    }
+}